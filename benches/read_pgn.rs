@@ -1,8 +1,10 @@
-use chessland_puzzle_generator::pgn::read_pgns;
+use chessland_puzzle_generator::domain::pgn::read_pgns_str;
 use criterion::{criterion_group, criterion_main, Criterion};
 
+const SAMPLE_PGN: &str = include_str!("fixtures/sample.pgn");
+
 fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("read pgn", |b| b.iter(|| read_pgns("Colle.pgn")));
+    c.bench_function("read pgn", |b| b.iter(|| read_pgns_str(SAMPLE_PGN)));
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -1,25 +1,279 @@
-use std::{env, error::Error};
+use std::{env, error::Error, fs, io, str::FromStr};
 
 use dotenvy::dotenv;
+use serde::Deserialize;
+
+/// Optional config file, values from the environment take precedence over it
+const CONFIG_FILE: &str = "config.toml";
+
+/// Environment variable pointing at a config file to use instead of `config.toml`
+const CONFIG_PATH_VAR: &str = "CONFIG_PATH";
 
 #[derive(Clone)]
 pub struct Config {
     pub host: String,
-    pub port: String,
+    pub port: u16,
 
     pub api_key: String,
     pub chessland_endpoint: String,
+    /// Whether generated puzzles are pushed to `chessland_endpoint`
+    pub publish_puzzles: bool,
+
+    /// Number of engines serving requests concurrently
+    pub engine_pool_size: usize,
+    /// Hash table budget in MB shared by all engines
+    pub total_hash_mb: Option<usize>,
+    /// Seconds between engine health checks
+    pub engine_check_interval_secs: u64,
+    /// Requests an engine may serve before it gets respawned
+    pub engine_max_requests: Option<u64>,
+    /// Directories holding Syzygy tablebases, separated like in `SyzygyPath`
+    pub syzygy_path: Option<String>,
+    /// Engine results kept for positions that come up again, 0 disabling the cache
+    pub eval_cache_size: usize,
+    /// Most games a single batch request may submit
+    pub max_batch_size: usize,
+    /// Largest request body accepted, in bytes
+    pub max_body_bytes: usize,
+    /// Most plies a submitted game may have
+    pub max_game_plies: usize,
+    /// Body field holding the game's moves, looked for before the built-in ones
+    pub pgn_field: Option<String>,
+}
+
+/// Values read from the config file, every one of them may be left out
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    server: ServerSection,
+    engine: EngineSection,
+}
+
+/// `[server]` section of the config file
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    host: Option<String>,
+    port: Option<u16>,
+    api_key: Option<String>,
+    chessland_endpoint: Option<String>,
+    publish_puzzles: Option<bool>,
+    max_batch_size: Option<usize>,
+    max_body_bytes: Option<usize>,
+    max_game_plies: Option<usize>,
+    pgn_field: Option<String>,
+}
+
+/// `[engine]` section of the config file
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct EngineSection {
+    pool_size: Option<usize>,
+    total_hash_mb: Option<usize>,
+    check_interval_secs: Option<u64>,
+    max_requests: Option<u64>,
+    syzygy_path: Option<String>,
+    eval_cache_size: Option<usize>,
+}
+
+impl FileConfig {
+    /// Reads the config file, a missing file reading as an empty one unless
+    /// it was asked for explicitly
+    fn read(path: &str, must_exist: bool) -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !must_exist => Ok(Self::default()),
+            Err(e) => Err(format!("can't read config file {path}: {e}").into()),
+        }
+    }
 }
 
 impl Config {
+    /// Loads the config from the environment and the config file at
+    /// `CONFIG_PATH`, or `config.toml` when unset, the environment winning
     pub fn load() -> Result<Self, Box<dyn Error>> {
-        dotenv()?;
+        dotenv().ok();
+        let (path, must_exist) = match env::var(CONFIG_PATH_VAR) {
+            Ok(path) => (path, true),
+            Err(_) => (CONFIG_FILE.to_string(), false),
+        };
+        let file = FileConfig::read(&path, must_exist)?;
+
+        Self::resolve(file, &path, |key| env::var(key).ok())
+    }
+
+    /// Loads the config from a TOML file alone, ignoring the environment
+    ///
+    /// # Arguments
+    /// * `path` - File with `[server]` and `[engine]` sections, see `config.example.toml`
+    pub fn from_toml(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = FileConfig::read(path, true)?;
+        Self::resolve(file, path, |_| None)
+    }
+
+    /// Combines the config file with the values `env` finds, which win
+    fn resolve(
+        file: FileConfig,
+        path: &str,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let source = Source { path, env };
+        let (server, engine) = (file.server, file.engine);
 
         Ok(Self {
-            host: env::var("HOST")?,
-            port: env::var("PORT")?,
-            api_key: env::var("API_KEY")?,
-            chessland_endpoint: env::var("CHESSLAND_ENDPOINT")?,
+            host: source.required("HOST", server.host)?,
+            port: source.required("PORT", server.port)?,
+            api_key: source.required("API_KEY", server.api_key)?,
+            chessland_endpoint: source.required("CHESSLAND_ENDPOINT", server.chessland_endpoint)?,
+            publish_puzzles: source
+                .optional("PUBLISH_PUZZLES", server.publish_puzzles)?
+                .unwrap_or(false),
+            engine_pool_size: source
+                .optional("ENGINE_POOL_SIZE", engine.pool_size)?
+                .unwrap_or(1)
+                .max(1),
+            total_hash_mb: source.optional("TOTAL_HASH_MB", engine.total_hash_mb)?,
+            engine_check_interval_secs: source
+                .optional("ENGINE_CHECK_INTERVAL_SECS", engine.check_interval_secs)?
+                .unwrap_or(60),
+            engine_max_requests: source.optional("ENGINE_MAX_REQUESTS", engine.max_requests)?,
+            syzygy_path: source.optional("SYZYGY_PATH", engine.syzygy_path)?,
+            eval_cache_size: source
+                .optional("EVAL_CACHE_SIZE", engine.eval_cache_size)?
+                .unwrap_or(10_000),
+            max_batch_size: source
+                .optional("MAX_BATCH_SIZE", server.max_batch_size)?
+                .unwrap_or(50),
+            max_body_bytes: source
+                .optional("MAX_BODY_BYTES", server.max_body_bytes)?
+                .unwrap_or(1024 * 1024),
+            max_game_plies: source
+                .optional("MAX_GAME_PLIES", server.max_game_plies)?
+                .unwrap_or(600),
+            pgn_field: source.optional("PGN_FIELD", server.pgn_field)?,
         })
     }
 }
+
+/// Where config values come from: variables looked up by `env`, falling
+/// back to the config file at `path`
+struct Source<'a, E> {
+    path: &'a str,
+    env: E,
+}
+
+impl<E: Fn(&str) -> Option<String>> Source<'_, E> {
+    /// Reads `key` from the environment, falling back to the config file value
+    fn optional<T>(&self, key: &str, file_value: Option<T>) -> Result<Option<T>, Box<dyn Error>>
+    where
+        T: FromStr,
+        T::Err: Error + 'static,
+    {
+        match (self.env)(key) {
+            Some(value) => match value.parse() {
+                Ok(parsed) => Ok(Some(parsed)),
+                Err(e) => Err(format!("{key} has an invalid value {value:?}: {e}").into()),
+            },
+            None => Ok(file_value),
+        }
+    }
+
+    fn required<T>(&self, key: &str, file_value: Option<T>) -> Result<T, Box<dyn Error>>
+    where
+        T: FromStr,
+        T::Err: Error + 'static,
+    {
+        self.optional(key, file_value)?.ok_or_else(|| {
+            format!(
+                "{key} is set neither in the environment nor in {}",
+                self.path
+            )
+            .into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/config.example.toml");
+
+    fn file(contents: &str) -> FileConfig {
+        toml::from_str(contents).unwrap()
+    }
+
+    fn minimal() -> FileConfig {
+        file(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 3000
+            api_key = "secret"
+            chessland_endpoint = "http://localhost/puzzles"
+            "#,
+        )
+    }
+
+    #[test]
+    fn example_config_loads() {
+        let config = Config::from_toml(EXAMPLE).unwrap();
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.api_key, "change-me");
+        assert_eq!(config.engine_pool_size, 1);
+        assert_eq!(config.eval_cache_size, 10_000);
+        assert_eq!(config.pgn_field, None);
+    }
+
+    #[test]
+    fn left_out_values_take_their_defaults() {
+        let config = Config::resolve(minimal(), "test.toml", |_| None).unwrap();
+        assert!(!config.publish_puzzles);
+        assert_eq!(config.max_batch_size, 50);
+        assert_eq!(config.max_body_bytes, 1024 * 1024);
+        assert_eq!(config.engine_check_interval_secs, 60);
+        assert_eq!(config.total_hash_mb, None);
+    }
+
+    #[test]
+    fn environment_wins_over_the_file() {
+        let env = |key: &str| match key {
+            "PORT" => Some("9000".to_string()),
+            "ENGINE_POOL_SIZE" => Some("4".to_string()),
+            _ => None,
+        };
+        let config = Config::resolve(minimal(), "test.toml", env).unwrap();
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.engine_pool_size, 4);
+        assert_eq!(config.host, "127.0.0.1");
+    }
+
+    #[test]
+    fn non_numeric_port_is_reported() {
+        let env = |key: &str| (key == "PORT").then(|| "http".to_string());
+        let Err(e) = Config::resolve(minimal(), "test.toml", env) else {
+            panic!("a non-numeric port was accepted");
+        };
+        let message = e.to_string();
+        assert!(message.starts_with("PORT has an invalid value \"http\""));
+
+        assert!(toml::from_str::<FileConfig>("[server]\nport = \"http\"").is_err());
+    }
+
+    #[test]
+    fn missing_required_value_names_the_file() {
+        let Err(e) = Config::resolve(FileConfig::default(), "test.toml", |_| None) else {
+            panic!("a config without a host was accepted");
+        };
+        assert_eq!(
+            e.to_string(),
+            "HOST is set neither in the environment nor in test.toml"
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<FileConfig>("[server]\nprot = 8080").is_err());
+        assert!(Config::from_toml("does-not-exist.toml").is_err());
+    }
+}
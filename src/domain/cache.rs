@@ -0,0 +1,230 @@
+use std::fmt::Debug;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use lru::LruCache;
+use shakmaty::fen::Fen;
+
+use super::notation::{self, PositionKey};
+use super::stockfish::Evaluation;
+
+/// What was asked of the engine about a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Query {
+    /// Static evaluation, see [`super::stockfish::eval_pos`]
+    StaticEval,
+    /// Evaluation by a search, see [`super::stockfish::eval_by_search_for_pos`]
+    SearchEval,
+    /// Best lines, see [`super::stockfish::top_lines_for_pos`]
+    TopLines(usize),
+}
+
+#[derive(Clone)]
+enum Answer {
+    Eval(Evaluation),
+    Lines(Vec<(String, Evaluation)>),
+}
+
+/// Least recently used engine results, keyed by position and search depth,
+/// so positions that recur across games and requests are analyzed only once
+///
+/// Positions are keyed without their move clocks, the same position reached
+/// on another move being searched alike.
+///
+/// The cache is shared by cloning an `Arc` of it into the generation options.
+pub struct EvalCache {
+    entries: Mutex<LruCache<(PositionKey, u8, Query), Answer>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl EvalCache {
+    /// # Arguments
+    /// * `capacity` - Most results kept, the least recently used being dropped first
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        EvalCache {
+            entries: Mutex::new(LruCache::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of lookups answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of lookups that had to ask the engine
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Looks up the evaluation of a position, running `compute` on a miss
+    ///
+    /// # Arguments
+    /// * `by_search` - Whether `compute` evaluates by a search rather than statically
+    pub fn evaluation<E>(
+        &self,
+        fen: &Fen,
+        depth: u8,
+        by_search: bool,
+        compute: impl FnOnce() -> Result<Evaluation, E>,
+    ) -> Result<Evaluation, E> {
+        let query = if by_search {
+            Query::SearchEval
+        } else {
+            Query::StaticEval
+        };
+        let answer = self.get_or_compute(fen, depth, query, || compute().map(Answer::Eval))?;
+        match answer {
+            Answer::Eval(eval) => Ok(eval),
+            Answer::Lines(_) => unreachable!("evaluation queries only store evaluations"),
+        }
+    }
+
+    /// Looks up the best `count` lines of a position, running `compute` on a miss
+    pub fn top_lines<E>(
+        &self,
+        fen: &Fen,
+        depth: u8,
+        count: usize,
+        compute: impl FnOnce() -> Result<Vec<(String, Evaluation)>, E>,
+    ) -> Result<Vec<(String, Evaluation)>, E> {
+        let query = Query::TopLines(count);
+        let answer = self.get_or_compute(fen, depth, query, || compute().map(Answer::Lines))?;
+        match answer {
+            Answer::Lines(lines) => Ok(lines),
+            Answer::Eval(_) => unreachable!("top lines queries only store lines"),
+        }
+    }
+
+    /// The lock isn't held while `compute` runs, so a slow search doesn't
+    /// hold up other requests. Positions the rules reject have no key and
+    /// are never kept.
+    fn get_or_compute<E>(
+        &self,
+        fen: &Fen,
+        depth: u8,
+        query: Query,
+        compute: impl FnOnce() -> Result<Answer, E>,
+    ) -> Result<Answer, E> {
+        let Ok(board) = notation::board_of(fen) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return compute();
+        };
+        let key = (notation::position_key(&board), depth, query);
+        if let Some(answer) = self.entries.lock().unwrap().get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(answer.clone());
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let answer = compute()?;
+        self.entries.lock().unwrap().put(key, answer.clone());
+        Ok(answer)
+    }
+}
+
+impl Debug for EvalCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries = self.entries.lock().unwrap();
+        f.debug_struct("EvalCache")
+            .field("len", &entries.len())
+            .field("capacity", &entries.cap())
+            .field("hits", &self.hits())
+            .field("misses", &self.misses())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::convert::Infallible;
+
+    use super::*;
+
+    fn start() -> Fen {
+        Fen::from_ascii(b"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap()
+    }
+
+    fn cache(capacity: usize) -> EvalCache {
+        EvalCache::new(NonZeroUsize::new(capacity).unwrap())
+    }
+
+    #[test]
+    fn second_lookup_is_served_from_the_cache() {
+        let cache = cache(8);
+        let searches = Cell::new(0);
+        let search = || {
+            searches.set(searches.get() + 1);
+            Ok::<_, Infallible>(vec![("e2e4".to_string(), Evaluation::Eval(0.3))])
+        };
+
+        let first = cache.top_lines(&start(), 12, 2, search).unwrap();
+        let second = cache.top_lines(&start(), 12, 2, search).unwrap();
+
+        assert_eq!(searches.get(), 1);
+        assert_eq!(first[0].0, second[0].0);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    }
+
+    #[test]
+    fn depth_and_query_are_part_of_the_key() {
+        let cache = cache(8);
+        let searches = Cell::new(0);
+        let search = || {
+            searches.set(searches.get() + 1);
+            Ok::<_, Infallible>(Evaluation::Eval(0.3))
+        };
+
+        cache.evaluation(&start(), 12, true, search).unwrap();
+        cache.evaluation(&start(), 14, true, search).unwrap();
+        cache.evaluation(&start(), 12, false, search).unwrap();
+
+        assert_eq!(searches.get(), 3);
+        assert_eq!(cache.hits(), 0);
+    }
+
+    #[test]
+    fn positions_differing_only_in_their_clocks_share_an_entry() {
+        let cache = cache(8);
+        let later =
+            Fen::from_ascii(b"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 8 5").unwrap();
+        let searches = Cell::new(0);
+        let search = || {
+            searches.set(searches.get() + 1);
+            Ok::<_, Infallible>(Evaluation::Eval(0.3))
+        };
+
+        cache.evaluation(&start(), 12, true, search).unwrap();
+        cache.evaluation(&later, 12, true, search).unwrap();
+
+        assert_eq!(searches.get(), 1);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    }
+
+    #[test]
+    fn failed_searches_are_not_kept() {
+        let cache = cache(8);
+        assert!(cache.evaluation(&start(), 12, true, || Err(())).is_err());
+
+        let eval = cache.evaluation(&start(), 12, true, || Ok::<_, ()>(Evaluation::Mate(2)));
+        assert!(matches!(eval, Ok(Evaluation::Mate(2))));
+        assert_eq!(cache.misses(), 2);
+    }
+
+    #[test]
+    fn least_recently_used_result_is_dropped() {
+        let cache = cache(1);
+        let other = Fen::from_ascii(b"4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let eval = || Ok::<_, Infallible>(Evaluation::Eval(0.0));
+
+        cache.evaluation(&start(), 12, true, eval).unwrap();
+        cache.evaluation(&other, 12, true, eval).unwrap();
+        cache.evaluation(&start(), 12, true, eval).unwrap();
+
+        assert_eq!((cache.hits(), cache.misses()), (0, 3));
+    }
+}
@@ -1,3 +1,10 @@
+pub mod cache;
+pub mod notation;
+pub mod openings;
+pub mod pgn;
+pub mod pool;
 pub mod puzzle;
+pub mod puzzle_set;
+pub mod rating;
 pub mod stockfish;
-mod pgn;
\ No newline at end of file
+pub mod themes;
\ No newline at end of file
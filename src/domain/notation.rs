@@ -0,0 +1,305 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use shakmaty::fen::{Epd, Fen};
+use shakmaty::san::{San, SanPlus};
+use shakmaty::uci::UciMove;
+use shakmaty::{CastlingMode, Chess, EnPassantMode, Move, Position};
+use tracing::info;
+
+#[derive(Debug)]
+pub struct NotationError(pub String);
+
+impl Display for NotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Converts a move in SAN to UCI notation
+///
+/// # Arguments
+/// * `san` - Move in standard algebraic notation, e.g. `Nbd7` or `O-O`
+/// * `board` - Position the move is played from
+///
+/// # Returns
+/// The move in UCI notation, e.g. `b8d7` or `e1g1`
+pub fn san_to_uci(san: &str, board: &Chess) -> Result<String, NotationError> {
+    let mov = san_to_move(san, board)?;
+    Ok(UciMove::from_move(mov, CastlingMode::Standard).to_string())
+}
+
+/// Converts a move in UCI to SAN notation, the reverse of [`san_to_uci`]
+///
+/// Castling is accepted both as the king's two-square move (`e1g1`) and as
+/// king-takes-rook (`e1h1`), and comes out as `O-O`/`O-O-O`. The SAN only
+/// disambiguates by file or rank when another piece of the same kind could
+/// reach the square, e.g. `Nbd7`
+///
+/// # Arguments
+/// * `uci` - Move in UCI notation, e.g. `e7e8q`
+/// * `board` - Position the move is played from
+///
+/// # Returns
+/// The move in standard algebraic notation including capture, promotion,
+/// check and mate markers, e.g. `dxe8=Q+`
+pub fn uci_to_san(uci: &str, board: &Chess) -> Result<String, NotationError> {
+    let mov = uci_to_move(uci, board)?;
+    Ok(SanPlus::from_move(board.clone(), mov).to_string())
+}
+
+/// Plays a move given in SAN on the board
+///
+/// # Arguments
+/// * `san` - Move in standard algebraic notation, castling also written with
+///   zeros, e.g. `0-0`
+/// * `board` - Position the move is played on, advanced in place
+/// * `mode` - How castling is encoded in UCI, king-takes-rook for Chess960
+///
+/// # Returns
+/// The played move in UCI notation
+pub fn play_san(san: &str, board: &mut Chess, mode: CastlingMode) -> Result<String, NotationError> {
+    let mov = san_to_move(san, board)?;
+    let uci = UciMove::from_move(mov, mode).to_string();
+    board.play_unchecked(mov);
+    Ok(uci)
+}
+
+fn san_to_move(san: &str, board: &Chess) -> Result<Move, NotationError> {
+    // Castling is written with zeros by some exporters, e.g. `0-0-0`
+    let normalized = if san.starts_with("0-0") {
+        san.replace('0', "O")
+    } else {
+        san.to_string()
+    };
+    let parsed = San::from_str(&normalized)
+        .map_err(|e| NotationError(format!("could not parse {san}: {e}")))?;
+
+    match parsed.to_move(board) {
+        Ok(mov) => Ok(mov),
+        Err(e) => match disambiguate(parsed, board) {
+            Some(mov) => {
+                let uci = UciMove::from_move(mov, CastlingMode::Standard);
+                info!("auto-disambiguated {san} ({e}) to {uci}");
+                Ok(mov)
+            }
+            None => Err(NotationError(format!("{san} is not playable: {e}"))),
+        },
+    }
+}
+
+/// Attempts to salvage a SAN move that doesn't resolve to exactly one legal move
+///
+/// Sloppy exporters omit or misplace disambiguation and capture markers, so the
+/// move is matched on piece, destination and promotion alone, using the file and
+/// rank hints only when they narrow the candidates down.
+///
+/// # Returns
+/// The only legal move matching the SAN, or `None` if it stays ambiguous
+fn disambiguate(san: San, board: &Chess) -> Option<Move> {
+    let San::Normal {
+        role,
+        file,
+        rank,
+        to,
+        promotion,
+        ..
+    } = san
+    else {
+        return None;
+    };
+
+    let candidates: Vec<Move> = board
+        .legal_moves()
+        .into_iter()
+        .filter(|m| m.role() == role && m.to() == to && m.promotion() == promotion)
+        .collect();
+
+    if let [only] = candidates.as_slice() {
+        return Some(*only);
+    }
+
+    let hinted: Vec<Move> = candidates
+        .into_iter()
+        .filter(|m| {
+            let from = m.from();
+            file.is_none_or(|f| from.is_some_and(|sq| sq.file() == f))
+                && rank.is_none_or(|r| from.is_some_and(|sq| sq.rank() == r))
+        })
+        .collect();
+
+    match hinted.as_slice() {
+        [only] => Some(*only),
+        _ => None,
+    }
+}
+
+/// Plays a move given in UCI on the board
+///
+/// # Arguments
+/// * `uci` - Move in UCI notation
+/// * `board` - Position the move is played on, advanced in place
+///
+/// # Returns
+/// The move that was played
+pub fn play_uci(uci: &str, board: &mut Chess) -> Result<Move, NotationError> {
+    let mov = uci_to_move(uci, board)?;
+    board.play_unchecked(mov);
+    Ok(mov)
+}
+
+/// Tells whether a move is forcing, i.e. a capture, a check or a promotion
+///
+/// # Arguments
+/// * `uci` - Move in UCI notation
+/// * `board` - Position the move is played from
+pub fn is_forcing(uci: &str, board: &Chess) -> Result<bool, NotationError> {
+    let mov = uci_to_move(uci, board)?;
+    if mov.is_capture() || mov.is_promotion() {
+        return Ok(true);
+    }
+
+    let mut after = board.clone();
+    after.play_unchecked(mov);
+    Ok(after.is_check())
+}
+
+/// Tells whether a move captures on the square the previous move landed on
+///
+/// # Arguments
+/// * `uci` - Move in UCI notation
+/// * `previous` - Move played just before it, in UCI notation
+/// * `board` - Position the move is played from
+pub fn is_recapture(uci: &str, previous: &str, board: &Chess) -> Result<bool, NotationError> {
+    let mov = uci_to_move(uci, board)?;
+    let landed_on = UciMove::from_str(previous)
+        .map_err(|e| NotationError(format!("could not parse {previous}: {e}")))?
+        .to();
+
+    Ok(mov.is_capture() && landed_on == Some(mov.to()))
+}
+
+fn uci_to_move(uci: &str, board: &Chess) -> Result<Move, NotationError> {
+    UciMove::from_str(uci)
+        .map_err(|e| NotationError(format!("could not parse {uci}: {e}")))?
+        .to_move(board)
+        .map_err(|e| NotationError(format!("{uci} is not playable: {e}")))
+}
+
+/// Parses a FEN into a playable standard chess position
+pub fn board_from_fen(fen: &str) -> Result<Chess, NotationError> {
+    let parsed =
+        Fen::from_str(fen).map_err(|e| NotationError(format!("could not parse fen {fen}: {e}")))?;
+    board_of(&parsed)
+}
+
+/// Turns a FEN into a playable standard chess position, rejecting illegal setups
+pub fn board_of(fen: &Fen) -> Result<Chess, NotationError> {
+    board_in_mode(fen, CastlingMode::Standard)
+}
+
+/// Turns a FEN into a playable position, with castling rights read for the
+/// given castling mode, e.g. Shredder-FEN rook files for Chess960
+pub fn board_in_mode(fen: &Fen, mode: CastlingMode) -> Result<Chess, NotationError> {
+    fen.clone()
+        .into_position(mode)
+        .map_err(|e| NotationError(format!("fen {fen} is not a legal position: {e}")))
+}
+
+pub fn fen_of(board: &Chess) -> Fen {
+    Fen::from_position(board, EnPassantMode::Legal)
+}
+
+/// Identity of a position: piece placement, side to move, castling rights and
+/// en passant square, leaving out the halfmove and fullmove clocks
+pub type PositionKey = Epd;
+
+/// Normalized key of a position, equal for the same position however it was
+/// reached; only a legally capturable en passant square is kept
+pub fn position_key(board: &Chess) -> PositionKey {
+    Epd::from_position(board, EnPassantMode::Legal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Position after 1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5, white can castle short
+    const ITALIAN: &str = "r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+
+    /// Position after 1. Nf3 d5 2. d3 e5, where both white knights reach d2
+    const KNIGHTS: &str = "rnbqkbnr/ppp2ppp/8/3pp3/8/3P1N2/PPP1PPPP/RNBQKB1R w KQkq - 0 3";
+
+    #[test]
+    fn castling_is_read_with_letters_or_zeros() {
+        let board = board_from_fen(ITALIAN).unwrap();
+        assert_eq!(san_to_uci("O-O", &board).unwrap(), "e1g1");
+        assert_eq!(san_to_uci("0-0", &board).unwrap(), "e1g1");
+    }
+
+    #[test]
+    fn zero_castling_is_played() {
+        let mut board = board_from_fen(ITALIAN).unwrap();
+        assert_eq!(
+            play_san("0-0", &mut board, CastlingMode::Standard).unwrap(),
+            "e1g1"
+        );
+        assert_eq!(board.turn(), shakmaty::Color::Black);
+    }
+
+    #[test]
+    fn sloppy_san_is_salvaged_when_only_one_move_fits() {
+        // Both knights reach d2, only the one on f3 takes on e5
+        let board = board_from_fen(KNIGHTS).unwrap();
+        assert_eq!(san_to_uci("Nbd2", &board).unwrap(), "b1d2");
+        assert_eq!(san_to_uci("Nbe5", &board).unwrap(), "f3e5");
+        assert!(san_to_uci("Nd2", &board).is_err());
+
+        // A pawn capture written without its file or capture marker
+        let board = board_from_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(san_to_uci("d5", &board).unwrap(), "e4d5");
+    }
+
+    #[test]
+    fn uci_is_written_as_san() {
+        let board = board_from_fen(ITALIAN).unwrap();
+        assert_eq!(uci_to_san("e1g1", &board).unwrap(), "O-O");
+        assert_eq!(uci_to_san("e1h1", &board).unwrap(), "O-O");
+
+        let board = board_from_fen(KNIGHTS).unwrap();
+        assert_eq!(uci_to_san("b1d2", &board).unwrap(), "Nbd2");
+        assert_eq!(uci_to_san("f3e5", &board).unwrap(), "Nxe5");
+
+        let board = board_from_fen("4r1k1/3P4/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        assert_eq!(uci_to_san("d7e8q", &board).unwrap(), "dxe8=Q+");
+        assert_eq!(uci_to_san("d7d8n", &board).unwrap(), "d8=N");
+    }
+
+    #[test]
+    fn position_key_ignores_the_clocks() {
+        let board = board_from_fen(ITALIAN).unwrap();
+        let later = board_from_fen(&ITALIAN.replace("4 4", "10 12")).unwrap();
+        assert_eq!(position_key(&board), position_key(&later));
+
+        // An en passant square nobody can capture on isn't part of the key
+        let after_e4 =
+            board_from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        let without_ep =
+            board_from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert_eq!(position_key(&after_e4), position_key(&without_ep));
+    }
+
+    #[test]
+    fn recapture_takes_on_the_square_just_landed_on() {
+        // 1. e4 d5 2. exd5
+        let board =
+            board_from_fen("rnbqkbnr/ppp1pppp/8/3P4/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2").unwrap();
+        assert!(is_recapture("d8d5", "e4d5", &board).unwrap());
+        assert!(!is_recapture("g8f6", "e4d5", &board).unwrap());
+
+        // Nxe5 captures, but not on c5 where the bishop just went
+        let board = board_from_fen(ITALIAN).unwrap();
+        assert!(!is_recapture("f3e5", "f8c5", &board).unwrap());
+        assert!(is_recapture("f3e5", "zz", &board).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use tracing::warn;
+
+use super::pgn::Pgn;
+
+/// Bundled ECO table, one opening per line: code, name and SAN moves
+/// separated by tabs
+const ECO_TABLE: &str = include_str!("eco.tsv");
+
+/// A named opening of the ECO classification
+#[derive(Debug)]
+pub struct Opening {
+    /// ECO code, e.g. `B20`
+    pub eco: String,
+    /// Opening name, the family first and then the variation, e.g.
+    /// `Sicilian Defense: Alapin Variation`
+    pub name: String,
+    /// Moves defining the opening, in UCI notation
+    moves: Vec<String>,
+}
+
+/// Writes an opening name as tags the way Lichess does, the family followed
+/// by the variation, e.g. `Sicilian_Defense Sicilian_Defense_Alapin_Variation`
+///
+/// # Arguments
+/// * `name` - Opening name, e.g. `Sicilian Defense: Alapin Variation`
+pub fn lichess_tags(name: &str) -> String {
+    let family = name.split(':').next().unwrap_or(name);
+    let mut tags = vec![tag(family)];
+    if family != name {
+        tags.push(tag(name));
+    }
+    tags.join(" ")
+}
+
+/// Turns a name into a tag, words joined by underscores and punctuation dropped
+fn tag(name: &str) -> String {
+    name.split_whitespace()
+        .map(|word| word.replace(|c: char| !c.is_alphanumeric() && c != '-', ""))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Finds the opening a game started with
+///
+/// # Arguments
+/// * `moves` - Moves of the game in UCI notation, played from the standard position
+///
+/// # Returns
+/// The opening sharing the longest sequence of first moves with the game,
+/// `None` if the game starts with no known opening
+pub fn classify(moves: &[String]) -> Option<&'static Opening> {
+    openings()
+        .iter()
+        .filter(|opening| moves.starts_with(&opening.moves))
+        .max_by_key(|opening| opening.moves.len())
+}
+
+/// The bundled ECO table, parsed on first use
+fn openings() -> &'static [Opening] {
+    static OPENINGS: OnceLock<Vec<Opening>> = OnceLock::new();
+    OPENINGS.get_or_init(|| ECO_TABLE.lines().filter_map(parse_line).collect())
+}
+
+fn parse_line(line: &str) -> Option<Opening> {
+    let mut columns = line.split('\t');
+    let (Some(eco), Some(name), Some(sans)) = (columns.next(), columns.next(), columns.next())
+    else {
+        warn!("skipping malformed ECO table line {line:?}");
+        return None;
+    };
+
+    match Pgn::from_str(sans) {
+        Ok(pgn) => Some(Opening {
+            eco: eco.to_string(),
+            name: name.to_string(),
+            moves: pgn.moves().clone(),
+        }),
+        Err(e) => {
+            warn!("skipping ECO table line {line:?}: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uci_moves(sans: &str) -> Vec<String> {
+        Pgn::from_str(sans).unwrap().moves().clone()
+    }
+
+    #[test]
+    fn every_table_line_is_read() {
+        assert_eq!(openings().len(), ECO_TABLE.lines().count());
+    }
+
+    #[test]
+    fn sicilian_is_classified() {
+        let opening = classify(&uci_moves("e4 c5 Nc3 Nc6")).unwrap();
+        assert_eq!(opening.eco, "B23");
+        assert_eq!(opening.name, "Sicilian Defense: Closed");
+
+        let opening = classify(&uci_moves("e4 c5 a3")).unwrap();
+        assert_eq!(opening.eco, "B20");
+        assert_eq!(opening.name, "Sicilian Defense");
+    }
+
+    #[test]
+    fn unknown_first_moves_have_no_opening() {
+        assert!(classify(&uci_moves("h4 h5")).is_none());
+        assert!(classify(&[]).is_none());
+    }
+
+    #[test]
+    fn names_become_lichess_tags() {
+        assert_eq!(
+            lichess_tags("Sicilian Defense: Alapin Variation"),
+            "Sicilian_Defense Sicilian_Defense_Alapin_Variation"
+        );
+        assert_eq!(lichess_tags("King's Gambit"), "Kings_Gambit");
+        assert_eq!(
+            lichess_tags("Sicilian Defense: Smith-Morra Gambit"),
+            "Sicilian_Defense Sicilian_Defense_Smith-Morra_Gambit"
+        );
+    }
+}
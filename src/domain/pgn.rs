@@ -1,7 +1,25 @@
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
 use std::iter::FromIterator;
 use std::str::FromStr;
 
+use flate2::read::MultiGzDecoder;
+use shakmaty::fen::Fen;
+use shakmaty::uci::UciMove;
+use shakmaty::{CastlingMode, Chess, Color};
+use tracing::warn;
+
+use super::notation::{self, NotationError};
+
+/// Annotation marking an en passant capture, either trailing the move or
+/// standing on its own after it
+const EN_PASSANT: &str = "e.p.";
+
+/// First bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
 #[derive(Debug)]
 pub struct InvalidNotationError(pub String);
 
@@ -11,17 +29,9 @@ impl Display for InvalidNotationError {
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum Side {
-    White(String),
-    Black(String),
-}
-
-impl Side {
-    fn mov_ref(&self) -> &str {
-        match self {
-            Side::White(mov) | Side::Black(mov) => mov,
-        }
+impl From<notation::NotationError> for InvalidNotationError {
+    fn from(err: notation::NotationError) -> Self {
+        InvalidNotationError(err.0)
     }
 }
 
@@ -46,147 +56,903 @@ impl Display for Pgn {
 }
 
 impl Pgn {
-    fn validate_move(mov: Side) -> Result<String, String> {
-        match mov {
-            Side::White(str) if str == "O-O" => Ok("e1g1".into()),
-            Side::White(str) if str == "O-O-O" => Ok("e1c1".into()),
-
-            Side::Black(str) if str == "O-O" => Ok("e8g8".into()),
-            Side::Black(str) if str == "O-O-O" => Ok("e8c8".into()),
-
-            Side::White(str) | Side::Black(str)
-                if ["o-o", "o-o-o", "0-0", "0-0-0"].iter().any(|a| a == &str) =>
-            {
-                Err(format!("expected O-O or O-O-O, got {str}"))
-            }
-            _ => Self::validate_mov_chars(mov.mov_ref()),
+    /// Parses a sequence of moves played from `start`
+    ///
+    /// Each move may be in UCI, e.g. `g1f3`, in SAN, e.g. `Nf3` or `Nbd7`, or
+    /// in long algebraic notation, e.g. `Ng1-f3`. Castling may be written with
+    /// zeros, e.g. `0-0`. Every move is replayed on the
+    /// board, so illegal moves are rejected however well-formed they are.
+    ///
+    /// # Arguments
+    /// * `s` - Moves separated by whitespace, move numbers allowed
+    /// * `start` - Position the first move is played from
+    ///
+    /// # Returns
+    /// The moves in UCI notation, or the first move that isn't playable
+    pub fn parse_from(s: &str, start: &Chess) -> Result<Self, InvalidNotationError> {
+        let mut board = start.clone();
+
+        s.split_whitespace()
+            .filter_map(move_token)
+            .enumerate()
+            .map(|(i, token)| {
+                Self::play_move(token, &mut board)
+                    .map_err(|e| InvalidNotationError(format!("{e}\nmove num:{}", i + 1)))
+            })
+            .collect()
+    }
+
+    /// Plays a move in any of the accepted notations on the board
+    ///
+    /// # Returns
+    /// The move in UCI notation, castling as the king's two-square move
+    fn play_move(token: &str, board: &mut Chess) -> Result<String, NotationError> {
+        if let Ok(mov) = notation::play_uci(token, board) {
+            return Ok(UciMove::from_move(mov, CastlingMode::Standard).to_string());
+        }
+
+        match notation::play_san(token, board, CastlingMode::Standard) {
+            Ok(uci) => Ok(uci),
+            Err(e) => notation::play_uci(&Self::sanitize_move(token), board)
+                .map(|mov| UciMove::from_move(mov, CastlingMode::Standard).to_string())
+                .map_err(|_| e),
         }
     }
 
-    fn validate_mov_chars(mov: &str) -> Result<String, String> {
-        let sanitized = Self::sanitize_move(mov.to_string());
-        Self::validate_sanitized_move(&sanitized)?;
-        Ok(sanitized)
+    /// Reduces a move in long algebraic notation, e.g. `Ng1-f3`, `e5xd6e.p.`
+    /// or `g7xh8=N+`, to its squares followed by the lowercase promotion piece
+    fn sanitize_move(mov: &str) -> String {
+        let mov = mov.trim_end_matches(['+', '#', '!', '?']);
+        let mov = mov.strip_suffix(EN_PASSANT).unwrap_or(mov);
+        let (mov, promotion) = mov.split_once('=').unwrap_or((mov, ""));
+        let mov = mov
+            .strip_prefix(|c: char| c.is_ascii_uppercase())
+            .unwrap_or(mov);
+
+        let mut sanitized: String = mov.chars().filter(|c| !"x-:".contains(*c)).collect();
+        sanitized.push_str(&promotion.to_ascii_lowercase());
+        // Promotions written without `=`, e.g. `e7e8Q`, keep their piece last
+        if let Some(piece) = sanitized.get(4..) {
+            let piece = piece.to_ascii_lowercase();
+            sanitized.replace_range(4.., &piece);
+        }
+        sanitized
     }
+}
 
-    fn sanitize_move(mut mov: String) -> String {
-        if mov.chars().next().is_some_and(|c| c.is_uppercase()) {
-            mov.remove(0);
+impl FromIterator<String> for Pgn {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        Pgn(iter.into_iter().collect())
+    }
+}
+
+impl<'a> FromIterator<&'a str> for Pgn {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+        Pgn(iter.into_iter().map(|s| s.to_string()).collect())
+    }
+}
+
+impl FromStr for Pgn {
+    type Err = InvalidNotationError;
+
+    /// Parses a sequence of moves played from the standard starting position,
+    /// see [`Pgn::parse_from`]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_from(s, &Chess::default())
+    }
+}
+
+/// Outcome of a game as recorded by its result marker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    /// Game marked with `*`, still ongoing or abandoned
+    Unknown,
+}
+
+impl GameResult {
+    /// All results other than a draw or an unknown outcome
+    pub const DECISIVE: [GameResult; 2] = [GameResult::WhiteWins, GameResult::BlackWins];
+
+    pub fn winner(&self) -> Option<Color> {
+        match self {
+            GameResult::WhiteWins => Some(Color::White),
+            GameResult::BlackWins => Some(Color::Black),
+            GameResult::Draw | GameResult::Unknown => None,
         }
-        mov.retain(|c| !"x+#=-".contains(c));
-        mov
     }
+}
+
+impl FromStr for GameResult {
+    type Err = InvalidNotationError;
 
-    fn validate_sanitized_move(mov: &str) -> Result<(), String> {
-        if mov.len() < 4 || mov.len() > 5 {
-            return Err(format!("expected {mov} to have length of 4 or 5"));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1-0" => Ok(GameResult::WhiteWins),
+            "0-1" => Ok(GameResult::BlackWins),
+            "1/2-1/2" => Ok(GameResult::Draw),
+            "*" => Ok(GameResult::Unknown),
+            _ => Err(InvalidNotationError(format!("{s} is not a game result"))),
         }
+    }
+}
+
+/// Tag pairs of a game, e.g. `[White "Morphy, Paul"]`, keyed by tag name
+#[derive(Debug, Clone, Default)]
+pub struct PgnHeaders(HashMap<String, String>);
+
+impl PgnHeaders {
+    /// Value of a tag, looked up by its exact name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn event(&self) -> Option<&str> {
+        self.get("Event")
+    }
+
+    pub fn site(&self) -> Option<&str> {
+        self.get("Site")
+    }
+
+    pub fn date(&self) -> Option<&str> {
+        self.get("Date")
+    }
+
+    pub fn white(&self) -> Option<&str> {
+        self.get("White")
+    }
+
+    pub fn black(&self) -> Option<&str> {
+        self.get("Black")
+    }
+
+    pub fn white_elo(&self) -> Option<u32> {
+        self.get("WhiteElo").and_then(|elo| elo.parse().ok())
+    }
+
+    pub fn black_elo(&self) -> Option<u32> {
+        self.get("BlackElo").and_then(|elo| elo.parse().ok())
+    }
+
+    pub fn result(&self) -> Option<GameResult> {
+        self.get("Result").and_then(|result| result.parse().ok())
+    }
+
+    /// Position the game starts from, as given by the `FEN` tag
+    pub fn fen(&self) -> Option<&str> {
+        self.get("FEN")
+    }
+
+    pub fn variant(&self) -> Option<&str> {
+        self.get("Variant")
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        self.0.insert(name, value);
+    }
+}
+
+/// A single validated game read from a PGN database
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub headers: PgnHeaders,
+    /// Moves of the mainline in UCI notation
+    pub moves: Vec<String>,
+    pub result: GameResult,
+    /// Position the game starts from, `None` for the standard one
+    pub start_fen: Option<String>,
+    /// `Chess960` for Fischer Random games, whose castling moves are encoded
+    /// as the king taking its own rook
+    pub castling_mode: CastlingMode,
+}
+
+impl Game {
+    /// Space separated UCI moves, as accepted by the puzzle generator
+    pub fn movetext(&self) -> String {
+        self.moves.join(" ")
+    }
+}
+
+/// Restricts which games are returned by the PGN reader
+#[derive(Debug, Clone, Default)]
+pub struct PgnFilter {
+    /// Accepted results, e.g. `GameResult::DECISIVE`; `None` keeps every game
+    pub results: Option<Vec<GameResult>>,
+    /// Stop reading after this many validated games
+    pub max_games: Option<usize>,
+}
+
+impl PgnFilter {
+    fn accepts(&self, result: &GameResult) -> bool {
+        self.results
+            .as_ref()
+            .is_none_or(|accepted| accepted.contains(result))
+    }
+}
+
+/// Reason a game of a PGN database couldn't be read
+#[derive(Debug, Clone)]
+pub struct PgnParseError {
+    /// Position of the game in the database, counting from 0
+    pub game_idx: usize,
+    /// Move that couldn't be played, `None` when the game failed before its moves
+    pub san: Option<String>,
+    pub reason: String,
+}
+
+impl Display for PgnParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "game {}: {}", self.game_idx, self.reason)
+    }
+}
+
+/// Reads every game from a PGN database
+///
+/// # Arguments
+/// * `file_path` - Path to the PGN file
+///
+/// # Returns
+/// Each game in order, or the reason it couldn't be read
+pub fn read_pgns(file_path: &str) -> io::Result<Vec<Result<Game, PgnParseError>>> {
+    read_pgns_filtered(file_path, &PgnFilter::default())
+}
+
+/// Reads every game from a PGN database, dropping games that can't be read
+///
+/// # Arguments
+/// * `file_path` - Path to the PGN file
+pub fn read_pgns_ok(file_path: &str) -> io::Result<Vec<Game>> {
+    Ok(read_pgns(file_path)?
+        .into_iter()
+        .filter_map(|game| game.inspect_err(|e| warn!("dropping {e}")).ok())
+        .collect())
+}
+
+/// Reads games from a PGN database, keeping only those accepted by the filter
+///
+/// Gzip-compressed databases, e.g. `.pgn.gz` archives, are decompressed on
+/// the fly.
+///
+/// # Arguments
+/// * `file_path` - Path to the PGN file
+/// * `filter` - Accepted results and the maximum number of games to read
+pub fn read_pgns_filtered(
+    file_path: &str,
+    filter: &PgnFilter,
+) -> io::Result<Vec<Result<Game, PgnParseError>>> {
+    collect_games(read_pgns_iter_filtered(file_path, filter)?)
+}
+
+/// Reads games from a PGN database lazily, one at a time, so memory stays
+/// bounded however large the database is
+///
+/// # Arguments
+/// * `file_path` - Path to the PGN file, possibly gzip-compressed
+///
+/// # Returns
+/// An iterator over each game in order, or the reason it couldn't be read
+pub fn read_pgns_iter(file_path: &str) -> io::Result<PgnGames<Box<dyn BufRead>>> {
+    read_pgns_iter_filtered(file_path, &PgnFilter::default())
+}
+
+/// Same as [`read_pgns_iter`], keeping only games accepted by the filter
+pub fn read_pgns_iter_filtered(
+    file_path: &str,
+    filter: &PgnFilter,
+) -> io::Result<PgnGames<Box<dyn BufRead>>> {
+    let mut file = BufReader::new(File::open(file_path)?);
+
+    let reader: Box<dyn BufRead> = if is_gzip(&mut file)? {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(file)
+    };
+
+    Ok(PgnGames::new(reader, filter.clone()))
+}
+
+/// Sniffs the gzip magic bytes without consuming them
+fn is_gzip(reader: &mut impl BufRead) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(&GZIP_MAGIC))
+}
+
+/// Reads every game from any source of PGN text, e.g. stdin or an upload
+///
+/// # Arguments
+/// * `reader` - Source of one or more games in PGN format
+pub fn read_pgns_from_reader(reader: impl Read) -> io::Result<Vec<Result<Game, PgnParseError>>> {
+    collect_games(PgnGames::new(BufReader::new(reader), PgnFilter::default()))
+}
+
+/// Parses every game from PGN text already held in memory
+///
+/// # Arguments
+/// * `contents` - One or more games in PGN format
+pub fn read_pgns_str(contents: &str) -> Vec<Result<Game, PgnParseError>> {
+    PgnGames::new(contents.as_bytes(), PgnFilter::default()).collect()
+}
+
+/// Collects every game, failing if the source couldn't be read to its end
+fn collect_games<R: BufRead>(
+    mut games: PgnGames<R>,
+) -> io::Result<Vec<Result<Game, PgnParseError>>> {
+    let parsed = games.by_ref().collect();
+    match games.io_error.take() {
+        Some(e) => Err(e),
+        None => Ok(parsed),
+    }
+}
 
-        let errors = mov.chars().enumerate().map(|(idx, character)| {
-            if (idx == 0 || idx == 2) && !Self::is_valid_file(character) {
-                return Err(format!(
-                    "first and third char must be any character between a-h, but got {character}"
-                ));
+/// Lazily parsed games of a PGN database
+///
+/// Games are split off the source line by line and validated as they are
+/// yielded. If the source fails, one last error is yielded and the
+/// iteration ends.
+pub struct PgnGames<R> {
+    reader: R,
+    filter: PgnFilter,
+    /// Games split off but not yet yielded, a line may end several games
+    pending: VecDeque<RawGame>,
+    /// Index the next game split off gets
+    next_idx: usize,
+    /// Games yielded successfully, counted against `filter.max_games`
+    read: usize,
+    headers: PgnHeaders,
+    movetext: String,
+    open: OpenGroups,
+    exhausted: bool,
+    io_error: Option<io::Error>,
+    reported_io_error: bool,
+}
+
+impl<R: BufRead> PgnGames<R> {
+    pub fn new(reader: R, filter: PgnFilter) -> Self {
+        PgnGames {
+            reader,
+            filter,
+            pending: VecDeque::new(),
+            next_idx: 0,
+            read: 0,
+            headers: PgnHeaders::default(),
+            movetext: String::new(),
+            open: OpenGroups::default(),
+            exhausted: false,
+            io_error: None,
+            reported_io_error: false,
+        }
+    }
+
+    /// Reads on until a game has been split off
+    fn next_raw(&mut self) -> Option<RawGame> {
+        let mut buffer = Vec::new();
+
+        loop {
+            if let Some(raw) = self.pending.pop_front() {
+                return Some(raw);
             }
-            if (idx == 1 || idx == 3) && !Self::is_valid_rank(character) {
-                return Err(format!(
-                    "second and fourth char must be any digit between 1-9, but got {character}"
-                ));
+            if self.exhausted {
+                return None;
             }
-            if idx == 4 {
-                let promotion = character.to_ascii_lowercase();
-                if !matches!(promotion, 'q' | 'r' | 'b' | 'n') {
-                    return Err(format!(
-                        "fifth char must be one of q/r/b/n, but got {promotion}"
-                    ));
+
+            buffer.clear();
+            match self.reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => {
+                    self.exhausted = true;
+                    if !self.movetext.trim().is_empty() {
+                        self.finish_game(GameResult::Unknown);
+                    }
+                }
+                // Databases aren't always valid UTF-8, the moves themselves are ASCII
+                Ok(_) => self.push_line(&String::from_utf8_lossy(&buffer)),
+                Err(e) => {
+                    self.exhausted = true;
+                    self.io_error = Some(e);
                 }
             }
+        }
+    }
 
-            Ok(())
-        }).filter_map(Result::err).fold(String::new(), |mut acc, err| {
-            if !acc.is_empty() {
-                acc.push('\n');
+    /// Splits a line into tag pairs, movetext and results
+    fn push_line(&mut self, line: &str) {
+        if self.open.is_empty()
+            && let Some((name, value)) = parse_tag(line)
+        {
+            self.headers.insert(name, value);
+            return;
+        }
+
+        let line = mainline_of(line, &mut self.open);
+        for token in line.split_whitespace().filter(|token| !is_nag(token)) {
+            match GameResult::from_str(token) {
+                Ok(result) => self.finish_game(result),
+                Err(_) => {
+                    self.movetext.push_str(token);
+                    self.movetext.push(' ');
+                }
             }
-            acc.push_str(&err);
-            acc
+        }
+    }
+
+    fn finish_game(&mut self, result: GameResult) {
+        self.pending.push_back(RawGame {
+            idx: self.next_idx,
+            headers: std::mem::take(&mut self.headers),
+            movetext: std::mem::take(&mut self.movetext),
+            result,
         });
+        self.next_idx += 1;
+    }
+}
 
-        if errors.is_empty() {
-            Ok(())
-        } else {
-            Err(errors)
+impl<R: BufRead> Iterator for PgnGames<R> {
+    type Item = Result<Game, PgnParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.read >= self.filter.max_games.unwrap_or(usize::MAX) {
+            return None;
+        }
+
+        loop {
+            let Some(raw) = self.next_raw() else {
+                return self.report_io_error();
+            };
+            if !self.filter.accepts(&raw.result) {
+                continue;
+            }
+
+            let game = parse_game(raw);
+            if game.is_ok() {
+                self.read += 1;
+            }
+            return Some(game);
         }
     }
+}
 
-    fn is_valid_file(c: char) -> bool {
-        matches!(c, 'a'..='h')
+impl<R> PgnGames<R> {
+    fn report_io_error(&mut self) -> Option<Result<Game, PgnParseError>> {
+        if self.reported_io_error {
+            return None;
+        }
+        let e = self.io_error.as_ref()?;
+        self.reported_io_error = true;
+
+        Some(Err(PgnParseError {
+            game_idx: self.next_idx,
+            san: None,
+            reason: format!("can't read the database: {e}"),
+        }))
     }
+}
+
+fn parse_game(raw: RawGame) -> Result<Game, PgnParseError> {
+    let (start, castling_mode) = start_position(raw.idx, &raw.headers)?;
+    let moves = validate(raw.idx, start.as_ref(), castling_mode, &move_sequence(&raw.movetext))?;
 
-    fn is_valid_rank(c: char) -> bool {
-        c.to_digit(10)
-            .is_some_and(|digit| (1..=10).contains(&digit))
+    Ok(Game {
+        headers: raw.headers,
+        moves,
+        result: raw.result,
+        start_fen: start.map(|fen| fen.to_string()),
+        castling_mode,
+    })
+}
+
+/// Tag pairs, movetext and result of a game before its moves are validated
+struct RawGame {
+    /// Position of the game in the database, counting from 0
+    idx: usize,
+    headers: PgnHeaders,
+    movetext: String,
+    result: GameResult,
+}
+
+/// Comments and variations left open at the end of a line, as both may span
+/// several lines
+#[derive(Default)]
+struct OpenGroups {
+    comments: usize,
+    variations: usize,
+}
+
+impl OpenGroups {
+    fn is_empty(&self) -> bool {
+        self.comments == 0 && self.variations == 0
     }
 }
 
-impl FromIterator<String> for Pgn {
-    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
-        Pgn(iter.into_iter().collect())
+/// Keeps only the mainline of a line of movetext, removing `{...}` comments
+/// and `(...)` variations, nested ones included, and `;` comments running to
+/// the end of the line
+///
+/// # Arguments
+/// * `line` - Line of movetext
+/// * `open` - Groups open at the start of the line, updated for the next line
+fn mainline_of(line: &str, open: &mut OpenGroups) -> String {
+    let mut mainline = String::with_capacity(line.len());
+
+    for c in line.chars() {
+        match c {
+            // Anything goes inside a comment, parentheses included
+            '{' => open.comments += 1,
+            '}' if open.comments > 0 => open.comments -= 1,
+            _ if open.comments > 0 => {}
+            ';' => break,
+            '(' => open.variations += 1,
+            ')' if open.variations > 0 => open.variations -= 1,
+            _ if open.variations > 0 => {}
+            _ => mainline.push(c),
+        }
     }
+
+    mainline
 }
 
-impl<'a> FromIterator<&'a str> for Pgn {
-    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
-        Pgn(iter.into_iter().map(|s| s.to_string()).collect())
+/// Tells whether a token is a numeric annotation glyph like `$1`
+fn is_nag(token: &str) -> bool {
+    token
+        .strip_prefix('$')
+        .is_some_and(|nag| !nag.is_empty() && nag.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Parses a tag pair line like `[Event "Casual game"]` into its name and value
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().trim_matches('"');
+    Some((name.to_string(), value.to_string()))
+}
+
+/// Tells whether a `Variant` tag names Fischer Random chess
+fn is_chess960(variant: &str) -> bool {
+    let normalized: String = variant
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    matches!(
+        normalized.as_str(),
+        "chess960" | "960" | "fischerandom" | "fischerrandom"
+    )
+}
+
+/// Reads the starting position and castling mode of a game from its tags
+///
+/// Games with a `FEN` tag start from it, the others from the standard
+/// position. A game with an unusable `FEN` tag or a Chess960 game without
+/// one is an error, as its starting position can't be known
+fn start_position(
+    game_idx: usize,
+    headers: &PgnHeaders,
+) -> Result<(Option<Fen>, CastlingMode), PgnParseError> {
+    let error = |reason: String| PgnParseError {
+        game_idx,
+        san: None,
+        reason,
+    };
+
+    let castling_mode = if headers.variant().is_some_and(is_chess960) {
+        CastlingMode::Chess960
+    } else {
+        CastlingMode::Standard
+    };
+
+    let Some(fen) = headers.fen() else {
+        if castling_mode == CastlingMode::Chess960 {
+            return Err(error("chess960 game without a FEN tag".to_string()));
+        }
+        return Ok((None, castling_mode));
+    };
+
+    match Fen::from_str(fen) {
+        Ok(fen) => Ok((Some(fen), castling_mode)),
+        Err(e) => Err(error(format!("invalid FEN tag {fen}: {e}"))),
     }
 }
 
-impl FromStr for Pgn {
-    type Err = InvalidNotationError;
+/// Splits movetext into SAN tokens, removing move numbers and move
+/// annotations
+///
+/// Exporters differ in spacing, so `1.e4`, `1. e4` and `1 . e4` are all
+/// reduced to `e4`, as are black's `1...e5` and `1... e5`, and `e4!?` too
+fn move_sequence(movetext: &str) -> Vec<String> {
+    movetext
+        .split_whitespace()
+        .filter_map(move_token)
+        .map(|san| san.trim_end_matches(['!', '?']))
+        .filter(|san| !san.is_empty())
+        .map(str::to_string)
+        .collect()
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (moves, errors) = s
-            .split_whitespace()
-            .filter(|s| {
-                if *s == "..." {
-                    return false;
-                }
+/// Strips a token of its leading move number, e.g. `12.` or `12...`, and of
+/// a trailing en passant annotation, e.g. `exd6e.p.`
+///
+/// # Returns
+/// The move, or `None` for a token that is only a move number or an annotation
+fn move_token(token: &str) -> Option<&str> {
+    let unnumbered = token.trim_start_matches(|c: char| c.is_ascii_digit());
+    let mov = if unnumbered.starts_with('.') {
+        unnumbered.trim_start_matches('.')
+    } else {
+        token
+    };
+    let mov = mov.strip_suffix(EN_PASSANT).unwrap_or(mov);
 
-                if s.ends_with('.') {
-                    let maybe_num = s.strip_suffix('.').unwrap();
-                    if maybe_num.parse::<u64>().is_ok() {
-                        return false;
-                    }
-                }
+    let is_number = mov.chars().all(|c| c.is_ascii_digit());
+    (!mov.is_empty() && !is_number).then_some(mov)
+}
 
-                true
-            })
-            .enumerate()
-            .map(|(i, raw_move)| {
-                let raw_move = raw_move.to_string();
-                let raw_move = if i % 2 == 0 {
-                    Side::White(raw_move)
-                } else {
-                    Side::Black(raw_move)
-                };
-
-                Pgn::validate_move(raw_move)
-                    .map_err(|e| InvalidNotationError(format!("{e}\nmove num:{}", i + 1)))
+/// Replays the SAN moves from the starting position and converts them to UCI
+///
+/// Fails on the first illegal move, or on an illegal starting position
+fn validate(
+    game_idx: usize,
+    start: Option<&Fen>,
+    castling_mode: CastlingMode,
+    sans: &[String],
+) -> Result<Vec<String>, PgnParseError> {
+    let mut board = match start {
+        Some(fen) => notation::board_in_mode(fen, castling_mode).map_err(|e| PgnParseError {
+            game_idx,
+            san: None,
+            reason: e.0,
+        })?,
+        None => Chess::default(),
+    };
+
+    sans.iter()
+        .map(|san| {
+            notation::play_san(san, &mut board, castling_mode).map_err(|e| PgnParseError {
+                game_idx,
+                san: Some(san.clone()),
+                reason: e.0,
             })
-            .fold((Vec::new(), String::new()), |mut acc, result| {
-                match result {
-                    Ok(mov) => acc.0.push(mov),
-                    Err(e) => {
-                        acc.1.push_str(&e.to_string());
-                        acc.1.push('\n');
-                    }
-                }
-                acc
-            });
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use shakmaty::Position;
+
+    use super::*;
+
+    const SAMPLE_PGN: &str = include_str!("../../benches/fixtures/sample.pgn");
+
+    /// Italian game in which both sides castle short, written with zeros
+    const ZERO_CASTLING: &str = "1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. 0-0 Nf6 5. d3 0-0";
+
+    /// Game whose third move takes en passant, annotated as such
+    const EN_PASSANT_GAME: &str = "1. e4 d5 2. e5 f5 3. exf6e.p. g6";
+
+    fn movetext_moves(movetext: &str) -> Vec<String> {
+        let games = read_pgns_str(&format!("[Result \"*\"]\n\n{movetext} *\n"));
+        games.into_iter().next().unwrap().unwrap().moves
+    }
+
+    #[test]
+    fn move_numbers_are_dropped_however_spaced() {
+        let expected = ["e2e4", "e7e5", "g1f3", "b8c6"];
+        for movetext in [
+            "1.e4 e5 2.Nf3 Nc6",
+            "1. e4 e5 2. Nf3 Nc6",
+            "1 . e4 e5 2 . Nf3 Nc6",
+            "1. e4 1... e5 2. Nf3 2...Nc6",
+        ] {
+            assert_eq!(movetext_moves(movetext), expected, "{movetext}");
+            assert_eq!(Pgn::from_str(movetext).unwrap().moves(), &expected, "{movetext}");
+        }
+    }
+
+    #[test]
+    fn en_passant_annotations_keep_the_move() {
+        let expected = ["e2e4", "d7d5", "e4e5", "f7f5", "e5f6", "g7g6"];
+        assert_eq!(movetext_moves(EN_PASSANT_GAME), expected);
+        assert_eq!(Pgn::from_str(EN_PASSANT_GAME).unwrap().moves(), &expected);
+
+        let spaced = "1. e4 d5 2. e5 f5 3. exf6 e.p. g6";
+        assert_eq!(movetext_moves(spaced), expected);
+        assert_eq!(Pgn::from_str(spaced).unwrap().moves(), &expected);
+    }
+
+    #[test]
+    fn unplayable_moves_are_reported_not_dropped() {
+        let games = read_pgns_str("[Result \"*\"]\n\n1. e4 d5 2. e5 f5 3. exd6e.p. *\n");
+        assert!(games[0].is_err());
+        assert!(Pgn::from_str("1. e4 d5 2. e5 f5 3. exd6e.p.").is_err());
+    }
+
+    #[test]
+    fn long_algebraic_moves_are_sanitized() {
+        assert_eq!(Pgn::sanitize_move("Ng1-f3"), "g1f3");
+        assert_eq!(Pgn::sanitize_move("e5xd6e.p."), "e5d6");
+        assert_eq!(Pgn::sanitize_move("e7xd8=Q+"), "e7d8q");
+        assert_eq!(Pgn::sanitize_move("g7xh8=N"), "g7h8n");
+        assert_eq!(Pgn::sanitize_move("e7e8Q"), "e7e8q");
+    }
+
+    #[test]
+    fn long_algebraic_en_passant_is_played() {
+        let pgn = Pgn::from_str("e2-e4 d7-d5 e4-e5 f7-f5 e5xf6e.p.").unwrap();
+        assert_eq!(pgn.moves()[4], "e5f6");
+    }
 
-        if errors.is_empty() {
-            Ok(Self(moves))
-        } else {
-            Err(InvalidNotationError(errors))
+    /// Source of PGN text counting how many bytes were taken from it
+    struct CountingReader<'a> {
+        inner: &'a [u8],
+        taken: Rc<Cell<usize>>,
+    }
+
+    impl Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.inner.read(buf)?;
+            self.taken.set(self.taken.get() + read);
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn max_games_stops_reading_early() {
+        let taken = Rc::new(Cell::new(0));
+        let reader = CountingReader {
+            inner: SAMPLE_PGN.as_bytes(),
+            taken: taken.clone(),
+        };
+        let filter = PgnFilter {
+            max_games: Some(1),
+            ..PgnFilter::default()
+        };
+
+        let games: Vec<_> = PgnGames::new(BufReader::with_capacity(64, reader), filter).collect();
+        assert_eq!(games.len(), 1);
+        assert!(games[0].is_ok());
+        assert!(taken.get() < SAMPLE_PGN.len() / 2);
+    }
+
+    #[test]
+    fn moves_accept_castling_with_zeros() {
+        let pgn = Pgn::from_str(ZERO_CASTLING).unwrap();
+        assert_eq!(pgn.moves()[6], "e1g1");
+        assert_eq!(pgn.moves()[9], "e8g8");
+    }
+
+    #[test]
+    fn databases_accept_castling_with_zeros() {
+        let games = read_pgns_str(&format!("[Result \"*\"]\n\n{ZERO_CASTLING} *\n"));
+        let game = games[0].as_ref().unwrap();
+        assert_eq!(game.moves.len(), 10);
+        assert_eq!(game.moves[6], "e1g1");
+        assert_eq!(game.moves[9], "e8g8");
+    }
+
+    #[test]
+    fn sample_database_parses() {
+        let games: Vec<Game> = read_pgns_str(SAMPLE_PGN)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(games.len(), 5);
+
+        let morphy = &games[0];
+        assert_eq!(morphy.headers.white(), Some("Paul Morphy"));
+        assert_eq!(morphy.result, GameResult::WhiteWins);
+        assert_eq!(morphy.moves[..3], ["e2e4", "e7e5", "g1f3"]);
+        // 11... Nbd7, disambiguated by file, and 17. Rd8#
+        assert_eq!(morphy.moves[21], "b8d7");
+        assert_eq!(morphy.moves.last().unwrap(), "d1d8");
+
+        let study = &games[3];
+        assert_eq!(study.start_fen.as_deref(), Some("8/8/8/4k3/8/8/4P3/4K3 w - - 0 1"));
+        assert_eq!(study.moves[..2], ["e1d2", "e5d4"]);
+    }
+
+    #[test]
+    fn filter_keeps_only_accepted_results() {
+        let filter = PgnFilter {
+            results: Some(GameResult::DECISIVE.to_vec()),
+            ..PgnFilter::default()
+        };
+        let games: Vec<_> = PgnGames::new(SAMPLE_PGN.as_bytes(), filter).collect();
+        assert_eq!(games.len(), 2);
+        assert!(games.iter().all(|game| game.as_ref().unwrap().result == GameResult::WhiteWins));
+    }
+
+    #[test]
+    fn comments_and_annotations_are_stripped() {
+        let movetext = concat!(
+            "1. e4 {+0.30/20 the most popular move} e5 $1 2. Nf3! ; attacks e5\n",
+            "Nc6 {spanning\nlines} 3. Bb5 $13 a6?! *"
+        );
+        let games = read_pgns_str(&format!("[Result \"*\"]\n\n{movetext}\n"));
+        let game = games[0].as_ref().unwrap();
+        assert_eq!(game.moves, ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6"]);
+    }
+
+    #[test]
+    fn nested_variations_leave_the_mainline() {
+        let movetext = concat!(
+            "1. e4 (1. d4 d5 (1... Nf6 2. c4 (2. Nf3 g6) e6) 2. c4) e5\n",
+            "(1... c5 2. Nf3 (2. c3)) 2. Nf3 Nc6 *"
+        );
+        assert_eq!(movetext_moves(movetext), ["e2e4", "e7e5", "g1f3", "b8c6"]);
+    }
+
+    #[test]
+    fn games_start_from_their_fen_tag() {
+        let pgn = concat!(
+            "[SetUp \"1\"]\n",
+            "[FEN \"4k2r/6P1/8/8/8/8/8/4K3 w k - 0 1\"]\n\n",
+            "1. gxh8=N Kf8 *\n",
+            "\n[SetUp \"1\"]\n",
+            "[FEN \"8/4P3/8/8/k7/8/8/4K3 w - - 0 1\"]\n\n",
+            "1. e8=Q+ Ka3 *\n"
+        );
+        let games = read_pgns_str(pgn);
+        assert_eq!(games[0].as_ref().unwrap().moves, ["g7h8n", "e8f8"]);
+        assert_eq!(games[1].as_ref().unwrap().moves, ["e7e8q", "a4a3"]);
+    }
+
+    #[test]
+    fn chess960_castling_takes_the_rook() {
+        // Kings on b1 and b8 castling long, rooks on a1 and a8 landing on d1 and d8
+        let games = read_pgns_str(SAMPLE_PGN);
+        let game = games[4].as_ref().unwrap();
+        assert_eq!(game.castling_mode, CastlingMode::Chess960);
+        assert_eq!(game.moves[8..10], ["b1a1", "b8a8"]);
+
+        // Played back in UCI, the moves reach the position the SAN reached
+        let start: Fen = game.start_fen.as_deref().unwrap().parse().unwrap();
+        let mut board = notation::board_in_mode(&start, CastlingMode::Chess960).unwrap();
+        for uci in &game.moves {
+            let mov = UciMove::from_str(uci).unwrap().to_move(&board).unwrap();
+            board.play_unchecked(mov);
         }
+        let end = "2krbnqr/ppb2ppp/2pnp3/3p4/3P4/2PNP3/PPB2PPP/2KRBNQR w - - 0 7";
+        assert_eq!(notation::fen_of(&board).to_string(), end);
+
+        let missing_fen = read_pgns_str("[Variant \"Chess960\"]\n\n1. e4 *\n");
+        assert!(missing_fen[0].is_err());
+    }
+
+    #[test]
+    fn gzipped_databases_read_like_plain_ones() {
+        let path = std::env::temp_dir().join(format!("sample-{}.pgn.gz", std::process::id()));
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(SAMPLE_PGN.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let gzipped: Vec<_> = read_pgns_iter(path.to_str().unwrap())
+            .unwrap()
+            .map(|game| game.unwrap().moves)
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        let plain: Vec<_> = read_pgns_str(SAMPLE_PGN)
+            .into_iter()
+            .map(|game| game.unwrap().moves)
+            .collect();
+        assert_eq!(gzipped, plain);
     }
 }
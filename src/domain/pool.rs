@@ -0,0 +1,246 @@
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+
+use tracing::{error, info};
+
+use crate::common::config::Config;
+use crate::domain::stockfish::Stockfish;
+
+/// Fixed set of engines shared by concurrent requests
+///
+/// Cloning the pool is cheap, clones share the same engines.
+#[derive(Clone)]
+pub struct StockfishPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    idle: Mutex<Vec<Stockfish>>,
+    returned: Condvar,
+    size: usize,
+    /// Config broken engines are respawned from, `None` keeping them as they are
+    conf: Option<Config>,
+}
+
+impl StockfishPool {
+    /// Builds a pool out of already spawned engines
+    pub fn new(engines: Vec<Stockfish>) -> Self {
+        let size = engines.len();
+        StockfishPool {
+            inner: Arc::new(PoolInner {
+                idle: Mutex::new(engines),
+                returned: Condvar::new(),
+                size,
+                conf: None,
+            }),
+        }
+    }
+
+    /// Spawns `engine_pool_size` engines configured from the config
+    pub fn from_config(conf: &Config) -> Result<Self, io::Error> {
+        let engines = (0..conf.engine_pool_size)
+            .map(|_| Stockfish::from_config(conf))
+            .collect::<Result<Vec<Stockfish>, io::Error>>()?;
+        info!("engine pool of {} started", engines.len());
+
+        let size = engines.len();
+        Ok(StockfishPool {
+            inner: Arc::new(PoolInner {
+                idle: Mutex::new(engines),
+                returned: Condvar::new(),
+                size,
+                conf: Some(conf.clone()),
+            }),
+        })
+    }
+
+    /// Number of engines in the pool, busy ones included
+    pub fn size(&self) -> usize {
+        self.inner.size
+    }
+
+    /// Checks out an engine, waiting until one is idle
+    ///
+    /// # Returns
+    /// A guard giving access to the engine, which returns it to the pool when dropped
+    pub fn acquire(&self) -> EngineGuard {
+        let mut idle = self.inner.idle.lock().unwrap();
+        loop {
+            if let Some(engine) = idle.pop() {
+                return EngineGuard {
+                    engine: Some(engine),
+                    pool: self.clone(),
+                };
+            }
+            idle = self.inner.returned.wait(idle).unwrap();
+        }
+    }
+
+    /// Checks out an engine only if one is idle right now
+    pub fn try_acquire(&self) -> Option<EngineGuard> {
+        let engine = self.inner.idle.lock().unwrap().pop()?;
+        Some(EngineGuard {
+            engine: Some(engine),
+            pool: self.clone(),
+        })
+    }
+
+    /// Runs `f` on every engine that is idle right now, busy engines are skipped
+    ///
+    /// Engines are checked out one at a time while `f` runs on them, so
+    /// requests only wait for the engine being worked on, not for the pool.
+    pub fn for_each_idle(&self, mut f: impl FnMut(&mut Stockfish)) {
+        let idle_now = self.inner.idle.lock().unwrap().len();
+        for _ in 0..idle_now {
+            let Some(mut engine) = self.try_acquire_least_recent() else {
+                break;
+            };
+            f(&mut engine);
+        }
+    }
+
+    /// Checks out the engine idle for the longest, if any, so engines
+    /// released in between go after those not yet visited
+    fn try_acquire_least_recent(&self) -> Option<EngineGuard> {
+        let mut idle = self.inner.idle.lock().unwrap();
+        if idle.is_empty() {
+            return None;
+        }
+        let engine = idle.remove(0);
+        Some(EngineGuard {
+            engine: Some(engine),
+            pool: self.clone(),
+        })
+    }
+
+    /// Waits for every engine to be returned, then quits them all
+    ///
+    /// The pool is left empty, so it must not be used afterwards.
+    ///
+    /// # Returns
+    /// The number of engines quit
+    pub fn shutdown(&self) -> usize {
+        let mut idle = self.inner.idle.lock().unwrap();
+        while idle.len() < self.inner.size {
+            idle = self.inner.returned.wait(idle).unwrap();
+        }
+
+        let engines: Vec<Stockfish> = idle.drain(..).collect();
+        drop(idle);
+        let count = engines.len();
+        // Dropping an engine sends `quit` and waits for its process to exit
+        drop(engines);
+        info!("engine pool of {count} shut down");
+        count
+    }
+
+    /// Takes an engine back, respawning it first if it stopped answering so
+    /// the next request doesn't get a broken engine
+    fn release(&self, mut engine: Stockfish) {
+        if let (true, Some(conf)) = (engine.is_broken(), &self.inner.conf) {
+            match Stockfish::from_config(conf) {
+                Ok(fresh) => {
+                    info!("respawned an engine that stopped answering");
+                    engine = fresh;
+                }
+                // The health check tries again later
+                Err(e) => error!("can't respawn an engine that stopped answering: {e}"),
+            }
+        }
+
+        self.inner.idle.lock().unwrap().push(engine);
+        self.inner.returned.notify_one();
+    }
+}
+
+/// Engine checked out of a [`StockfishPool`]
+pub struct EngineGuard {
+    engine: Option<Stockfish>,
+    pool: StockfishPool,
+}
+
+impl Deref for EngineGuard {
+    type Target = Stockfish;
+
+    fn deref(&self) -> &Self::Target {
+        self.engine.as_ref().expect("engine is only taken on drop")
+    }
+}
+
+impl DerefMut for EngineGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.engine.as_mut().expect("engine is only taken on drop")
+    }
+}
+
+impl Drop for EngineGuard {
+    fn drop(&mut self) {
+        if let Some(engine) = self.engine.take() {
+            self.pool.release(engine);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::{env, fs, process, thread};
+
+    use super::*;
+    use crate::domain::stockfish::tests::fake_engine;
+
+    fn pool_of(size: usize) -> StockfishPool {
+        StockfishPool::new((0..size).map(|_| fake_engine("")).collect())
+    }
+
+    #[test]
+    fn health_sweep_leaves_other_engines_available() {
+        let pool = pool_of(2);
+        let mut visited = 0;
+
+        pool.for_each_idle(|engine| {
+            visited += 1;
+            engine.is_ready().unwrap();
+            // The pool isn't locked while an engine is being checked
+            assert!(pool.try_acquire().is_some());
+        });
+
+        assert_eq!(visited, 2);
+        assert!(pool.try_acquire().is_some());
+    }
+
+    #[test]
+    fn engines_go_back_to_the_pool() {
+        let pool = pool_of(1);
+        let engine = pool.acquire();
+        assert!(pool.try_acquire().is_none());
+
+        drop(engine);
+        assert!(pool.try_acquire().is_some());
+        assert_eq!(pool.shutdown(), 1);
+    }
+
+    #[test]
+    fn shutdown_waits_for_busy_engines_and_quits_them() {
+        let quit_marker = env::temp_dir().join(format!("fakefish-quit-{}", process::id()));
+        let _ = fs::remove_file(&quit_marker);
+        let quit_arm = format!(
+            "\n    quit) echo quit >> {}; exit 0;;",
+            quit_marker.display()
+        );
+        let pool = StockfishPool::new(vec![fake_engine(&quit_arm), fake_engine(&quit_arm)]);
+
+        let busy = pool.acquire();
+        let returner = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(busy);
+        });
+
+        assert_eq!(pool.shutdown(), 2);
+        returner.join().unwrap();
+        assert!(pool.try_acquire().is_none());
+        assert_eq!(fs::read_to_string(&quit_marker).unwrap(), "quit\nquit\n");
+        fs::remove_file(&quit_marker).unwrap();
+    }
+}
@@ -1,13 +1,28 @@
 use core::f32;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::io;
 use std::ops::RangeInclusive;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
+use shakmaty::fen::{Epd, Fen};
+use shakmaty::{CastlingMode, Chess, Color, EnPassantMode, Position};
+use tracing::{debug, info, warn};
 
+use crate::domain::cache::EvalCache;
+use crate::domain::notation::{self, NotationError, PositionKey};
+use crate::domain::openings;
+use crate::domain::rating::{self, RatingFactors};
 use crate::domain::stockfish;
-use crate::domain::stockfish::{Evaluation, Stockfish};
+use crate::domain::stockfish::{Evaluation, Stockfish, StockfishError};
+use crate::domain::themes::{self, PuzzleTheme};
 
-use super::pgn::{InvalidNotationError, Pgn};
+use super::pgn::{Game, InvalidNotationError, Pgn};
 
 /// Represents a chess puzzle with position, and solution moves
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,48 +30,648 @@ pub struct Puzzle {
     pub moves: Vec<Move>,
     #[serde(rename = "startPositionOfPuzzle")]
     pub start_pos: usize,
+    /// FEN of the position at `start_pos`, so clients can render the board
+    /// without replaying the moves
+    #[serde(rename = "startFen", default)]
+    pub fen: String,
+    /// Every move but the solution loses significantly
+    #[serde(rename = "onlyMove", default)]
+    pub only_move: bool,
+    /// First moves other than the solution's that come within the
+    /// unique-solution margin of it, accepted as solving the puzzle too
+    #[serde(rename = "alternativeMoves", default, skip_serializing_if = "Vec::is_empty")]
+    pub alternative_moves: Vec<Move>,
+    /// Tactical motifs found in the solution
+    #[serde(default)]
+    pub themes: Vec<PuzzleTheme>,
+    /// What the solution achieves, e.g. a forced mate
+    #[serde(rename = "solutionKind", default)]
+    pub solution_kind: SolutionKind,
+    /// Estimated difficulty on an Elo-like scale, see [`rating::rate_puzzle`]
+    #[serde(default)]
+    pub rating: u32,
+    /// Level the puzzle was generated for, `None` for puzzles built by hand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<PuzzleLevel>,
+    /// Position `moves` are played from, `None` for the standard one
+    #[serde(rename = "initialFen", default, skip_serializing_if = "Option::is_none")]
+    pub initial_fen: Option<String>,
+    /// ECO code of the opening the game started with, e.g. `B20`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eco: Option<String>,
+    /// Name of the opening the game started with, e.g. `Sicilian Defense`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opening: Option<String>,
+    /// Solution from the start position, the solver's and the opponent's
+    /// moves alternating, so clients can check each solver move and play the
+    /// reply; the same moves `moves` ends with
+    #[serde(default)]
+    pub solution: Vec<Move>,
+    /// Every candidate position the puzzle was picked from, when the
+    /// generation was asked to explain itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug: Option<Vec<CandidateAnalysis>>,
 }
 
+/// Analysis of a candidate position, surfaced to explain why a puzzle was picked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CandidateAnalysis {
+    /// Moves played to reach the position
+    pub ply: usize,
+    pub fen: String,
+    /// Move played in the game from the position, in UCI notation
+    #[serde(rename = "playedMove", default, skip_serializing_if = "Option::is_none")]
+    pub played_move: Option<String>,
+    /// Engine's best move in the position, in UCI notation
+    #[serde(rename = "bestMove")]
+    pub best_move: String,
+    /// Evaluation of the position in pawns from white's point of view, `None`
+    /// when the side to move is in check
+    #[serde(default)]
+    pub eval: Option<f32>,
+    /// Swing in pawns brought by the best move, `None` when a mate appears
+    /// or changes hands
+    #[serde(default)]
+    pub delta: Option<f32>,
+    /// Eval in pawns the played move lost compared to the best one
+    #[serde(rename = "playedLoss", default)]
+    pub played_loss: f32,
+}
+
+impl Puzzle {
+    /// Builds a puzzle straight from its start position and solution, without
+    /// running the engine
+    ///
+    /// # Arguments
+    /// * `fen` - Position the solver starts from
+    /// * `uci_moves` - Solution moves in UCI notation separated by spaces, e.g. `e2e4 e7e5`
+    ///
+    /// # Returns
+    /// The puzzle, once every solution move has been replayed legally from `fen`
+    pub fn from_fen_and_moves(fen: &str, uci_moves: &str) -> Result<Puzzle, NotationError> {
+        let start = notation::board_from_fen(fen)?;
+        let mut board = start.clone();
+        let solution: Vec<String> = uci_moves.split_whitespace().map(str::to_string).collect();
+
+        let moves = solution
+            .iter()
+            .map(|uci| {
+                notation::play_uci(uci, &mut board)?;
+                Move::from_str(uci).map_err(|_| NotationError(format!("{uci} is not a UCI move")))
+            })
+            .collect::<Result<Vec<Move>, NotationError>>()?;
+
+        if moves.is_empty() {
+            return Err(NotationError("puzzle has no solution move".to_string()));
+        }
+        let themes = themes::detect_themes(&start, &solution)?;
+        let (solver_moves, quiet_moves) = count_solver_moves(&start, &solution)?;
+        // Without an engine, neither the depth needed nor the swing are known
+        let rating = rating::rate_puzzle(&RatingFactors {
+            find_depth: 0,
+            swing: f32::INFINITY,
+            solver_moves,
+            quiet_moves,
+        });
+        let solution_kind = if board.is_checkmate() {
+            SolutionKind::Mate(u8::try_from(moves.len().div_ceil(2)).unwrap_or(u8::MAX))
+        } else {
+            SolutionKind::Material
+        };
+
+        Ok(Puzzle {
+            solution: moves.clone(),
+            moves,
+            start_pos: 0,
+            fen: notation::fen_of(&start).to_string(),
+            only_move: false,
+            alternative_moves: Vec::new(),
+            themes,
+            solution_kind,
+            rating,
+            level: None,
+            initial_fen: Some(fen.to_string()),
+            eco: None,
+            opening: None,
+            debug: None,
+        })
+    }
+
+    /// Side that has to find the solution
+    pub fn side_to_move(&self) -> Color {
+        let first_to_move = self
+            .initial_fen
+            .as_deref()
+            .and_then(|fen| Fen::from_str(fen).ok())
+            .map_or(Color::White, |fen| fen.as_setup().turn);
+
+        if self.start_pos.is_multiple_of(2) {
+            first_to_move
+        } else {
+            !first_to_move
+        }
+    }
+
+    /// Moves played to reach the puzzle position
+    pub fn setup_moves(&self) -> &[Move] {
+        &self.moves[..self.start_pos]
+    }
+
+    /// Moves the solver is expected to find, starting with the first solution move
+    pub fn solution_moves(&self) -> &[Move] {
+        &self.moves[self.start_pos..]
+    }
+
+    /// FEN of the position the solver starts from
+    pub fn start_fen(&self) -> Result<Fen, NotationError> {
+        Ok(notation::fen_of(&self.start_board(None)?))
+    }
+
+    /// Normalized key of the start position, for comparing puzzles
+    pub fn position_key(&self) -> Result<PositionKey, NotationError> {
+        Ok(notation::position_key(&self.start_board(None)?))
+    }
+
+    /// Solution moves in UCI notation separated by spaces
+    pub fn solution_uci(&self) -> String {
+        self.solution_moves()
+            .iter()
+            .map(Move::to_string)
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// Strips the puzzle down to its start position and solution
+    pub fn to_minimal(&self) -> Result<MinimalPuzzle, NotationError> {
+        Ok(MinimalPuzzle {
+            fen: self.start_fen()?.to_string(),
+            solution: self.solution_uci(),
+        })
+    }
+
+    /// Renders the puzzle as an EPD record with a `bm` opcode
+    ///
+    /// # Arguments
+    /// * `id` - Number used in the `id` opcode, e.g. `id "puzzle 3";`
+    ///
+    /// # Returns
+    /// A line like `<epd> bm Nf7+ Qh5; id "puzzle 3";`, listing the
+    /// solution's first move and every alternative move as best moves
+    pub fn to_epd(&self, id: usize) -> Result<String, NotationError> {
+        let board = self.start_board(None)?;
+
+        let best_move = self
+            .solution_moves()
+            .first()
+            .ok_or_else(|| NotationError("puzzle has no solution move".to_string()))?;
+        let best_moves = std::iter::once(best_move)
+            .chain(&self.alternative_moves)
+            .map(|mv| notation::uci_to_san(&mv.to_string(), &board))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let epd = Epd::from_position(&board, EnPassantMode::Legal);
+        Ok(format!("{epd} bm {}; id \"puzzle {id}\";", best_moves.join(" ")))
+    }
+
+    /// Renders the puzzle as a PGN game for review in a PGN viewer
+    ///
+    /// The setup moves make up the main line, played from the puzzle's
+    /// initial position, and the solution follows in a comment so the game
+    /// still ends where the solver takes over
+    ///
+    /// # Returns
+    /// The PGN, headers included, e.g. `1. e4 e5 2. Nf3 { Solution: 2... Nc6 } *`
+    pub fn to_pgn(&self) -> Result<String, NotationError> {
+        let mut board = match &self.initial_fen {
+            Some(fen) => notation::board_from_fen(fen)?,
+            None => Chess::default(),
+        };
+
+        let mut pgn = String::from("[Event \"Generated Puzzle\"]\n");
+        if let Some(fen) = &self.initial_fen {
+            pgn.push_str(&format!("[SetUp \"1\"]\n[FEN \"{fen}\"]\n"));
+        }
+        pgn.push_str("[Result \"*\"]\n\n");
+
+        let setup = san_movetext(self.setup_moves(), &mut board)?;
+        if !setup.is_empty() {
+            pgn.push_str(&setup);
+            pgn.push(' ');
+        }
+        let solution = san_movetext(self.solution_moves(), &mut board)?;
+        pgn.push_str(&format!("{{ Solution: {solution} }} *\n"));
+
+        Ok(pgn)
+    }
+
+    /// Renders the puzzle as a row of the Lichess puzzle CSV, see
+    /// [`LICHESS_CSV_HEADER`] for the columns
+    ///
+    /// Columns the generator knows nothing about, like the rating deviation,
+    /// popularity or game URL, are left blank
+    ///
+    /// # Arguments
+    /// * `id` - Value of the `PuzzleId` column
+    ///
+    /// # Returns
+    /// The row, without a trailing newline
+    pub fn to_lichess_csv_row(&self, id: usize) -> Result<String, NotationError> {
+        let mut themes: Vec<String> = self.themes.iter().map(PuzzleTheme::to_string).collect();
+        match self.solution_kind {
+            SolutionKind::Mate(n) => themes.extend(["mate".to_string(), format!("mateIn{n}")]),
+            SolutionKind::Equalizing => themes.push("equality".to_string()),
+            SolutionKind::Material => {}
+        }
+
+        Ok(format!(
+            "{id},{},{},{},,,,{},,{}",
+            self.start_fen()?,
+            self.solution_uci(),
+            self.rating,
+            themes.join(" "),
+            self.opening.as_deref().map(openings::lichess_tags).unwrap_or_default()
+        ))
+    }
+
+    /// Reconstructs the position the solver starts from
+    ///
+    /// # Arguments
+    /// * `start_fen` - Position the game started from, `None` for the puzzle's
+    ///   own initial position, or the standard one if it has none
+    ///
+    /// # Returns
+    /// The board after replaying the setup moves
+    pub fn start_board(&self, start_fen: Option<&str>) -> Result<Chess, NotationError> {
+        let mut board = match start_fen.or(self.initial_fen.as_deref()) {
+            Some(fen) => notation::board_from_fen(fen)?,
+            None => Chess::default(),
+        };
+        for mov in self.setup_moves() {
+            notation::play_uci(&mov.to_string(), &mut board)?;
+        }
+        Ok(board)
+    }
+}
+
+/// What playing a puzzle's solution achieves
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SolutionKind {
+    /// The solution wins material or a decisive advantage
+    #[default]
+    Material,
+    /// The solution mates in the given number of moves
+    Mate(u8),
+    /// The solution only brings a worse position back to roughly level
+    Equalizing,
+}
+
+/// Converts moves to numbered SAN movetext, e.g. `12... Qxd5 13. Nc3`
+///
+/// # Arguments
+/// * `moves` - Moves to convert
+/// * `board` - Position the first move is played from, advanced past the last
+fn san_movetext(moves: &[Move], board: &mut Chess) -> Result<String, NotationError> {
+    let mut tokens = Vec::new();
+
+    for (idx, mov) in moves.iter().enumerate() {
+        let uci = mov.to_string();
+        let number = board.fullmoves();
+        match board.turn() {
+            Color::White => tokens.push(format!("{number}.")),
+            Color::Black if idx == 0 => tokens.push(format!("{number}...")),
+            Color::Black => {}
+        }
+        tokens.push(notation::uci_to_san(&uci, board)?);
+        notation::play_uci(&uci, board)?;
+    }
+
+    Ok(tokens.join(" "))
+}
+
+/// Bandwidth-friendly form of a puzzle, without setup moves or metadata
 #[derive(Debug, Serialize, Deserialize)]
+pub struct MinimalPuzzle {
+    pub fen: String,
+    /// Space separated UCI moves
+    pub solution: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Move {
     from: String,
     to: String,
     promotion: Option<String>,
 }
 
+impl Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.from, self.to)?;
+        if let Some(promotion) = &self.promotion {
+            write!(f, "{promotion}")?;
+        }
+        Ok(())
+    }
+}
+
 pub struct InvalidMoveFormat;
 
 impl FromStr for Move {
     type Err = InvalidMoveFormat;
 
+    /// Parses a move in UCI notation, e.g. `e2e4` or `e7e8q`, rejecting
+    /// squares off the board and promotions to anything but q/r/b/n
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.len() == 4 {
-            let (from, to) = s.split_at(2);
-            return Ok(Move {
-                from: from.to_string(),
-                to: to.to_string(),
-                promotion: None,
-            });
+        if !s.is_ascii() || !(4..=5).contains(&s.len()) {
+            return Err(InvalidMoveFormat);
+        }
+
+        let (from, rest) = s.split_at(2);
+        let (to, promotion) = rest.split_at(2);
+        if !is_square(from) || !is_square(to) {
+            return Err(InvalidMoveFormat);
+        }
+
+        let promotion = match promotion {
+            "" => None,
+            "q" | "r" | "b" | "n" => Some(promotion.to_string()),
+            _ => return Err(InvalidMoveFormat),
+        };
+
+        Ok(Move {
+            from: from.to_string(),
+            to: to.to_string(),
+            promotion,
+        })
+    }
+}
+
+/// Tells whether `s` names a square, a file a-h followed by a rank 1-8
+fn is_square(s: &str) -> bool {
+    matches!(s.as_bytes(), [b'a'..=b'h', b'1'..=b'8'])
+}
+
+/// Reasons puzzle generation can fail
+#[derive(Debug)]
+pub enum GenerationError {
+    /// The submitted moves don't form a legal game
+    InvalidPgn(String),
+    /// The submitted position isn't a legal one
+    InvalidPosition(String),
+    /// The game doesn't have enough moves to pick candidate positions from
+    GameTooShort(usize),
+    /// The game has more plies than the configured limit, given second
+    GameTooLong(usize, usize),
+    /// The ply window to analyze is empty or goes past the end of the game
+    InvalidWindow(String),
+    /// No candidate position satisfied the generation options
+    NoTacticFound(String),
+    /// The engine misbehaved or answered with something unusable
+    EngineError(String),
+    Internal(String),
+}
+
+impl Display for GenerationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenerationError::InvalidPgn(e) => write!(f, "{e}"),
+            GenerationError::InvalidPosition(e) => write!(f, "{e}"),
+            GenerationError::GameTooShort(len) => {
+                write!(f, "game is too short to generate a puzzle from: {len} moves")
+            }
+            GenerationError::GameTooLong(len, max) => {
+                write!(f, "game has {len} plies, more than the limit of {max}")
+            }
+            GenerationError::InvalidWindow(e) => write!(f, "{e}"),
+            GenerationError::NoTacticFound(e) => write!(f, "{e}"),
+            GenerationError::EngineError(e) => write!(f, "engine error: {e}"),
+            GenerationError::Internal(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<io::Error> for GenerationError {
+    fn from(err: io::Error) -> Self {
+        GenerationError::EngineError(err.to_string())
+    }
+}
+
+impl From<StockfishError> for GenerationError {
+    fn from(err: StockfishError) -> Self {
+        GenerationError::EngineError(err.to_string())
+    }
+}
+
+impl From<InvalidNotationError> for GenerationError {
+    fn from(err: InvalidNotationError) -> Self {
+        GenerationError::InvalidPgn(err.0)
+    }
+}
+
+/// Header row of the Lichess puzzle CSV
+pub const LICHESS_CSV_HEADER: &str =
+    "PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags";
+
+/// Fewest moves a game needs for candidate positions to be sampled from it
+const MIN_GAME_LEN: usize = 4;
+
+/// Eval in pawns by which every alternative must trail the best move for it
+/// to count as the only move
+const ONLY_MOVE_GAP: f32 = 2.0;
+
+/// Default eval in pawns by which the second best move must trail the best
+/// one for the solution not to be ambiguous
+const UNIQUE_SOLUTION_GAP: f32 = 1.5;
+
+/// Most pieces, kings included, a position may have to be covered by Syzygy tablebases
+const TABLEBASE_PIECES: usize = 7;
+
+/// Fewest plies between the start of a puzzle and the start or end of
+/// another one from the same game
+const PUZZLE_SPACING: usize = 4;
+
+/// Eval in pawns within which a position counts as level, for telling
+/// equalizing solutions apart from winning ones
+const EQUALITY_MARGIN: f32 = 1.0;
+
+/// Eval in pawns the opponent's last move must have given away for the
+/// position to count as following a blunder
+const BLUNDER_LOSS: f32 = 2.0;
+
+/// Which positions of a game puzzles are built from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GenerationMode {
+    /// Any position where the best move swings the eval
+    #[default]
+    BestMove,
+    /// Only positions right after the opponent blundered, the solver having
+    /// to find the refutation
+    PunishBlunder,
+}
+
+/// Difficulty of the generated puzzle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PuzzleLevel {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+}
+
+impl PuzzleLevel {
+    pub fn as_number(&self) -> u8 {
+        match self {
+            PuzzleLevel::Easy => 1,
+            PuzzleLevel::Medium => 2,
+            PuzzleLevel::Hard => 3,
         }
-        if s.len() == 5 {
-            let (from, to_and_prom) = s.split_at(2);
-            let (to, prom) = to_and_prom.split_at(2);
-            return Ok(Move {
-                from: from.to_string(),
-                to: to.to_string(),
-                promotion: Some(prom.to_string()),
-            });
+    }
+
+    /// Search depth used when no explicit depth is requested, so harder
+    /// puzzles get a more thorough analysis
+    pub fn default_depth(&self) -> u8 {
+        match self {
+            PuzzleLevel::Easy => 8,
+            PuzzleLevel::Medium => 12,
+            PuzzleLevel::Hard => 16,
         }
+    }
 
-        Err(InvalidMoveFormat)
+    /// Length in plies of the solution, the solver's moves and the replies
+    /// between them: one solver move for easy puzzles, two for medium and
+    /// three for hard ones, so 1, 3 and 5 plies as solutions end on a solver move
+    pub fn solution_plies(&self) -> usize {
+        2 * usize::from(self.as_number()) - 1
     }
 }
 
+#[derive(Debug)]
+pub struct InvalidLevelError(pub String);
+
+impl Display for InvalidLevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for PuzzleLevel {
+    type Err = InvalidLevelError;
+
+    /// Accepts level names case-insensitively, their initials and their numbers
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "easy" | "e" | "1" => Ok(PuzzleLevel::Easy),
+            "medium" | "m" | "2" => Ok(PuzzleLevel::Medium),
+            "hard" | "h" | "3" => Ok(PuzzleLevel::Hard),
+            _ => Err(InvalidLevelError(format!(
+                "unknown level {s}, expected one of easy/medium/hard, e/m/h or 1/2/3"
+            ))),
+        }
+    }
+}
+
+/// Tuning knobs for puzzle generation
+#[derive(Debug, Clone, Default)]
+pub struct GenerationOptions {
+    pub level: PuzzleLevel,
+    /// Which positions of the game are candidates
+    pub mode: GenerationMode,
+    /// Search depth overriding the level's default depth
+    pub depth: Option<u8>,
+    /// Wall-clock budget per best-move search; when set, the search deepens
+    /// iteratively up to the search depth instead of searching it directly
+    pub time_budget: Option<Duration>,
+    /// Rank candidates by how much the move actually played in the game lost
+    /// for its side, rather than by how much the best move swings the eval
+    pub blunders_only: bool,
+    /// Only accept candidates whose solution move is a capture, check or promotion
+    pub forcing_only: bool,
+    /// Accept candidates whose solution move merely takes back on the square
+    /// the opponent just moved to; always the case when punishing blunders,
+    /// taking the blundered piece often being the refutation
+    pub allow_recaptures: bool,
+    /// Only consider positions where this side is to move
+    pub for_side: Option<Color>,
+    /// First ply, counted from 0, of the window candidates are taken from;
+    /// with `to_ply` also unset, a random range past the opening is analyzed
+    pub from_ply: Option<usize>,
+    /// Last ply of the window candidates are taken from, the game's last
+    /// one when unset
+    pub to_ply: Option<usize>,
+    /// Only consider positions with few enough pieces to be in the tablebases
+    pub endgame_only: bool,
+    /// The engine has Syzygy tablebases configured, so endgame positions are
+    /// evaluated by search, which probes them, rather than statically
+    pub tablebases: bool,
+    /// Accepted length in plies of the forced solution line; when unset the
+    /// solution is the single best move. Mates are always solved up to the
+    /// mating move, however long that takes
+    pub solution_plies: Option<RangeInclusive<usize>>,
+    /// Accept candidates whose second best move is nearly as good as the best
+    pub allow_ambiguous: bool,
+    /// Eval in pawns by which the best move must beat the second best for
+    /// the solution to count as unique, [`UNIQUE_SOLUTION_GAP`] when unset
+    pub unique_margin: Option<f32>,
+    /// Evaluate positions by searching them, so swings are measured by the
+    /// tactics they contain rather than by the static evaluation
+    pub search_eval: bool,
+    /// Most plies a game may have to be analyzed, unlimited when unset
+    pub max_plies: Option<usize>,
+    /// Seed for the random choices made while generating, so the same game
+    /// and seed always give the same puzzle; unset draws a fresh seed
+    pub seed: Option<u64>,
+    /// Engine results shared with other generations, every position being
+    /// analyzed afresh when unset
+    pub cache: Option<Arc<EvalCache>>,
+    /// Log the analysis of every candidate position and attach it to the puzzle
+    pub debug: bool,
+}
+
 /// Holds data about a specific chess position
 struct PositionData {
     pos: usize,
     best_mv: String,
+    /// Evaluation of the position in pawns from white's point of view
+    eval: Option<f32>,
     delta: f32,
+    /// Eval lost by the side to move when playing the game move instead of the best one
+    played_loss: f32,
+    /// Whether the best move is a capture, check or promotion
+    forcing: bool,
+    /// Whether the best move captures on the square of the opponent's last move
+    recapture: bool,
+    /// What the best move achieves
+    kind: SolutionKind,
+}
+
+impl GenerationOptions {
+    /// Options for puzzles of the given level, the solution being a forced
+    /// line exactly as long as the level asks for
+    pub fn for_level(level: PuzzleLevel) -> Self {
+        let plies = level.solution_plies();
+        GenerationOptions {
+            level,
+            solution_plies: Some(plies..=plies),
+            ..Default::default()
+        }
+    }
+
+    /// Search depth for best-move analysis, the explicit one winning over the level default
+    pub fn depth(&self) -> u8 {
+        self.depth.unwrap_or_else(|| self.level.default_depth())
+    }
+}
+
+impl PositionData {
+    fn score(&self, options: &GenerationOptions) -> f32 {
+        if options.blunders_only {
+            self.played_loss
+        } else {
+            self.delta
+        }
+    }
 }
 
 // impl Display for Puzzle {
@@ -75,76 +690,846 @@ struct PositionData {
 ///
 /// # Arguments
 /// * `pgn` - Sequence of moves in UCI notation to analyze
+/// * `options` - Tuning knobs for the analysis
 /// * `stockfish` - Mutable reference to a Stockfish engine instance
 ///
 /// # Returns
 /// A Puzzle struct containing the generated puzzle
 pub fn generate_puzzle_by_position_analysis(
     moves: &str,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Puzzle, GenerationError> {
+    generate_puzzle_from_position(None, moves, options, stockfish)
+}
+
+/// Same as [`generate_puzzle_by_position_analysis`] for a game that starts
+/// from a custom position, e.g. a study or an endgame composition
+///
+/// # Arguments
+/// * `start_fen` - Position the game starts from, `None` for the standard one
+/// * `moves` - Sequence of moves in UCI notation played from `start_fen`
+pub fn generate_puzzle_from_position(
+    start_fen: Option<&str>,
+    moves: &str,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Puzzle, GenerationError> {
+    let game = AnalyzedGame::new(start_fen, moves, options, stockfish)?;
+    let analysis = options.debug.then(|| game.analysis());
+
+    let (best_position, solution) =
+        pick_candidate(game.candidates, &game.fens, options, stockfish)?;
+
+    let mut puzzle = build_puzzle(
+        game.pgn.moves(),
+        &game.fens,
+        start_fen,
+        best_position,
+        solution,
+        options,
+        stockfish,
+    )?;
+    puzzle.debug = analysis;
+    Ok(puzzle)
+}
+
+/// Generates a puzzle out of a single position rather than a game, the
+/// solution starting with the best move in the position
+///
+/// # Arguments
+/// * `fen` - Position the solver starts from
+/// * `options` - Tuning knobs for the analysis
+/// * `stockfish` - Mutable reference to a Stockfish engine instance
+///
+/// # Returns
+/// The puzzle, with no setup moves and `fen` as its initial position
+pub fn generate_puzzle_from_fen(
+    fen: &str,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Puzzle, GenerationError> {
+    let board = notation::board_from_fen(fen)
+        .map_err(|e| GenerationError::InvalidPosition(e.to_string()))?;
+    let fens = vec![notation::fen_of(&board)];
+    stockfish.begin_game()?;
+
+    let candidate = analyze_pos(0, None, &fens[0], None, options, stockfish)?.ok_or_else(|| {
+        GenerationError::NoTacticFound("the position has no legal move".to_string())
+    })?;
+    let (candidate, solution) = pick_candidate([candidate], &fens, options, stockfish)?;
+
+    build_puzzle(&[], &fens, Some(fen), candidate, solution, options, stockfish)
+}
+
+/// Generates up to `max` puzzles from distinct tactical moments of one game
+///
+/// Candidates are taken best scored first, skipping those whose position is
+/// within [`PUZZLE_SPACING`] plies of, or inside the solution of, a puzzle
+/// already picked, as well as repeats of an already picked position
+///
+/// # Arguments
+/// * `moves` - Sequence of moves in UCI notation to analyze
+/// * `max` - Most puzzles to generate
+/// * `options` - Tuning knobs for the analysis
+/// * `stockfish` - Mutable reference to a Stockfish engine instance
+///
+/// # Returns
+/// The puzzles, best scored first, or an error if not even one was found
+pub fn generate_puzzles(
+    moves: &str,
+    max: usize,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Vec<Puzzle>, GenerationError> {
+    let game = AnalyzedGame::new(None, moves, options, stockfish)?;
+    let mut candidates = game.candidates.into_iter();
+
+    let mut puzzles = Vec::new();
+    let mut spans: Vec<RangeInclusive<usize>> = Vec::new();
+    let mut keys: HashSet<PositionKey> = HashSet::new();
+
+    while puzzles.len() < max {
+        let remaining = candidates
+            .by_ref()
+            .filter(|candidate| !spans.iter().any(|span| too_close(span, candidate.pos)));
+        let picked = pick_candidate(remaining, &game.fens, options, stockfish);
+        let (candidate, solution) = match picked {
+            Ok(picked) => picked,
+            Err(GenerationError::NoTacticFound(_)) if !puzzles.is_empty() => break,
+            Err(e) => return Err(e),
+        };
+
+        let span = candidate.pos..=candidate.pos + solution.len();
+        if spans.iter().any(|picked| too_close(&span, *picked.start())) {
+            continue;
+        }
+        let key = notation::board_of(&game.fens[candidate.pos])
+            .map(|board| notation::position_key(&board))
+            .map_err(|e| GenerationError::Internal(e.to_string()))?;
+        if !keys.insert(key) {
+            continue;
+        }
+
+        spans.push(span);
+        puzzles.push(build_puzzle(
+            game.pgn.moves(),
+            &game.fens,
+            None,
+            candidate,
+            solution,
+            options,
+            stockfish,
+        )?);
+    }
+
+    Ok(puzzles)
+}
+
+/// Counts the moves the solver has to find in a solution, and how many of
+/// them are quiet, i.e. neither a capture, a check nor a promotion
+///
+/// # Returns
+/// The number of solver moves, then the number of quiet ones
+fn count_solver_moves(
+    start: &Chess,
+    solution: &[String],
+) -> Result<(usize, usize), NotationError> {
+    let mut board = start.clone();
+    let mut solver_moves = 0;
+    let mut quiet_moves = 0;
+
+    for (ply, uci) in solution.iter().enumerate() {
+        if ply.is_multiple_of(2) {
+            solver_moves += 1;
+            if !notation::is_forcing(uci, &board)? {
+                quiet_moves += 1;
+            }
+        }
+        notation::play_uci(uci, &mut board)?;
+    }
+
+    Ok((solver_moves, quiet_moves))
+}
+
+/// Finds the shallowest search depth at which `best_mv` clearly stands out,
+/// doubling the depth from 1 up to the analysis depth
+fn find_depth(
+    fen: &Fen,
+    best_mv: &str,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<u8, GenerationError> {
+    let margin = options.unique_margin.unwrap_or(UNIQUE_SOLUTION_GAP);
+    let mut depth = 1;
+
+    while depth < options.depth() {
+        let lines = top_lines(fen, depth, 2, options, stockfish)?;
+        let best_first = lines.first().is_some_and(|(mv, _)| mv == best_mv);
+        if best_first && holds_alone(scores(&lines), margin) {
+            return Ok(depth);
+        }
+        depth = depth.saturating_mul(2);
+    }
+
+    Ok(options.depth())
+}
+
+/// Tells whether a puzzle starting at `pos` would overlap the puzzle spanning `span`
+fn too_close(span: &RangeInclusive<usize>, pos: usize) -> bool {
+    pos + PUZZLE_SPACING > *span.start() && pos < span.end() + PUZZLE_SPACING
+}
+
+/// A game replayed and analyzed, ready for candidates to be picked from
+struct AnalyzedGame {
+    pgn: Pgn,
+    /// Positions of the game by ply
+    fens: Vec<Fen>,
+    /// Candidate positions matching the options, best scored first
+    candidates: Vec<PositionData>,
+}
+
+impl AnalyzedGame {
+    fn new(
+        start_fen: Option<&str>,
+        moves: &str,
+        options: &GenerationOptions,
+        stockfish: &mut Stockfish,
+    ) -> Result<Self, GenerationError> {
+        let start = start_fen
+            .map(notation::board_from_fen)
+            .transpose()
+            .map_err(|e| GenerationError::InvalidPgn(e.to_string()))?
+            .unwrap_or_default();
+        let pgn = Pgn::parse_from(moves, &start)?;
+        if pgn.moves().len() < MIN_GAME_LEN {
+            return Err(GenerationError::GameTooShort(pgn.moves().len()));
+        }
+        if let Some(max) = options.max_plies.filter(|max| pgn.moves().len() > *max) {
+            return Err(GenerationError::GameTooLong(pgn.moves().len(), max));
+        }
+        let fens = fens_by_ply(start, &pgn)?;
+        stockfish.begin_game()?;
+
+        let mut rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        let mut candidates = candidate_range(&pgn, options, &mut rng)?
+            .filter(|move_idx| {
+                options
+                    .for_side
+                    .is_none_or(|side| side == fens[*move_idx].as_setup().turn)
+            })
+            .filter(|move_idx| !options.endgame_only || is_endgame(&fens[*move_idx]))
+            .filter_map(|move_idx| {
+                if options.mode == GenerationMode::PunishBlunder {
+                    match follows_blunder(&fens, move_idx, options, stockfish) {
+                        Ok(true) => {}
+                        Ok(false) => return None,
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                analyze_pos(
+                    move_idx,
+                    move_idx.checked_sub(1).map(|prev| pgn.moves()[prev].as_str()),
+                    &fens[move_idx],
+                    Some(&fens[move_idx + 1]),
+                    options,
+                    stockfish,
+                )
+                .transpose()
+            })
+            .collect::<Result<Vec<PositionData>, GenerationError>>()?;
+
+        candidates.retain(|candidate| {
+            (!options.forcing_only || candidate.forcing)
+                && (options.allow_recaptures
+                    || options.mode == GenerationMode::PunishBlunder
+                    || !candidate.recapture)
+        });
+        candidates.sort_by(|x, y| y.score(options).total_cmp(&x.score(options)));
+
+        let game = AnalyzedGame {
+            pgn,
+            fens,
+            candidates,
+        };
+        if options.debug {
+            for candidate in game.analysis() {
+                debug!(?candidate, "candidate position");
+            }
+        }
+        Ok(game)
+    }
+
+    /// The analysis of every candidate, best scored first
+    fn analysis(&self) -> Vec<CandidateAnalysis> {
+        self.candidates
+            .iter()
+            .map(|candidate| CandidateAnalysis {
+                ply: candidate.pos,
+                fen: self.fens[candidate.pos].to_string(),
+                played_move: self.pgn.moves().get(candidate.pos).cloned(),
+                best_move: candidate.best_mv.clone(),
+                eval: candidate.eval,
+                delta: Some(candidate.delta).filter(|delta| delta.is_finite()),
+                played_loss: candidate.played_loss,
+            })
+            .collect()
+    }
+}
+
+/// Turns a picked candidate and its solution into a puzzle
+///
+/// # Arguments
+/// * `game_moves` - Moves of the game, those leading to the candidate become the setup moves
+/// * `fens` - Positions of the game by ply
+fn build_puzzle(
+    game_moves: &[String],
+    fens: &[Fen],
+    start_fen: Option<&str>,
+    best_position: PositionData,
+    solution: Vec<String>,
+    options: &GenerationOptions,
     stockfish: &mut Stockfish,
-) -> Result<Puzzle, InvalidNotationError> {
-    let pgn = Pgn::from_str(moves)?;
+) -> Result<Puzzle, GenerationError> {
+    let fen = &fens[best_position.pos];
+    let lines = top_lines(fen, options.depth(), 2, options, stockfish)?;
+    let only_move = holds_alone(scores(&lines), ONLY_MOVE_GAP);
+    let margin = options.unique_margin.unwrap_or(UNIQUE_SOLUTION_GAP);
+    let alternative_moves = match solution.first() {
+        Some(first) => moves_within(&lines, margin)
+            .filter(|mv| mv != first)
+            .map(Move::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| GenerationError::EngineError("malformed engine move".to_string()))?,
+        None => Vec::new(),
+    };
+    let board = notation::board_of(fen).map_err(|e| GenerationError::Internal(e.to_string()))?;
+    let themes = themes::detect_themes(&board, &solution)
+        .map_err(|e| GenerationError::EngineError(e.to_string()))?;
+    let (solver_moves, quiet_moves) = count_solver_moves(&board, &solution)
+        .map_err(|e| GenerationError::EngineError(e.to_string()))?;
+    let find_depth = match solution.first() {
+        Some(best_mv) => find_depth(fen, best_mv, options, stockfish)?,
+        None => 0,
+    };
+    let rating = rating::rate_puzzle(&RatingFactors {
+        find_depth,
+        swing: best_position.delta,
+        solver_moves,
+        quiet_moves,
+    });
 
-    let best_position = rand_range_of_moves(&pgn)
-        .map(|move_idx| analyze_pos(move_idx, &pgn, stockfish))
-        .max_by(|x, y| x.delta.total_cmp(&y.delta))
-        .expect("always valid");
+    // Only games played from the standard position follow a named opening
+    let opening = start_fen.is_none().then(|| openings::classify(game_moves)).flatten();
 
-    let mut puzzle_moves: Vec<String> = pgn
-        .moves()
+    let mut puzzle_moves: Vec<String> = game_moves
         .iter()
         .take(best_position.pos)
         .map(|a| a.to_string())
         .collect();
 
-    puzzle_moves.push(best_position.best_mv);
+    puzzle_moves.extend(solution.iter().cloned());
     
     let final_moves: Result<Vec<Move>, InvalidMoveFormat> = puzzle_moves.iter().map(|mov| Move::from_str(mov)).collect();
-    match final_moves {
-        Ok(moves) => {
+    let solution_line: Result<Vec<Move>, InvalidMoveFormat> =
+        solution.iter().map(|mov| Move::from_str(mov)).collect();
+    match final_moves.and_then(|moves| Ok((moves, solution_line?))) {
+        Ok((moves, solution_line)) => {
             Ok(Puzzle {
                 start_pos: best_position.pos,
+                fen: fens[best_position.pos].to_string(),
                 moves,
+                only_move,
+                alternative_moves,
+                themes,
+                solution_kind: best_position.kind,
+                rating,
+                level: Some(options.level),
+                initial_fen: start_fen.map(str::to_string),
+                eco: opening.map(|opening| opening.eco.clone()),
+                opening: opening.map(|opening| opening.name.clone()),
+                solution: solution_line,
+                debug: None,
             })
         },
-        Err(_) => Err(InvalidNotationError("unexpected error on final stage of move generation".to_string())),
+        Err(_) => Err(GenerationError::Internal("unexpected error on final stage of move generation".to_string())),
     }
 }
 
-fn analyze_pos(last_move: usize, moves: &Pgn, stockfish: &mut Stockfish) -> PositionData {
-    let base_moves = moves
-        .moves()
-        .iter()
-        .take(last_move)
-        .cloned()
-        .collect::<Pgn>()
-        .to_string();
+/// Outcome of generating puzzles over a collection of games
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub puzzles: Vec<Puzzle>,
+    /// Indices of games in which no position matched the generation options
+    pub without_tactic: Vec<usize>,
+    /// Indices of games that couldn't be analyzed, with the reason
+    pub failed: Vec<(usize, GenerationError)>,
+}
+
+/// Generates one puzzle per game, keeping track of games that yielded none
+///
+/// # Arguments
+/// * `games` - Games read from a PGN database, analyzed as they come so a
+///   lazy reader never holds more than one game
+/// * `options` - Tuning knobs for the analysis, e.g. `for_side` to only look
+///   for tactics of one color
+/// * `stockfish` - Mutable reference to a Stockfish engine instance
+pub fn generate_puzzles_for_games(
+    games: impl IntoIterator<Item = Game>,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> BatchReport {
+    let mut report = BatchReport::default();
+
+    for (idx, game) in games.into_iter().enumerate() {
+        if game.castling_mode == CastlingMode::Chess960 {
+            warn!("game {idx} is a chess960 game, which the generator doesn't support");
+            report.failed.push((
+                idx,
+                GenerationError::InvalidPgn("chess960 games aren't supported".to_string()),
+            ));
+            continue;
+        }
+
+        let start_fen = game.start_fen.as_deref();
+        match generate_puzzle_from_position(start_fen, &game.movetext(), options, stockfish) {
+            Ok(puzzle) => report.puzzles.push(puzzle),
+            Err(GenerationError::NoTacticFound(reason)) => {
+                info!("game {idx} yielded no puzzle: {reason}");
+                report.without_tactic.push(idx);
+            }
+            Err(e) => {
+                warn!("game {idx} could not be analyzed: {e}");
+                report.failed.push((idx, e));
+            }
+        }
+    }
+
+    report
+}
+
+/// Analyzes the position reached after `last_move` moves
+///
+/// # Arguments
+/// * `last_move` - Number of moves played to reach the position
+/// * `previous_mv` - The opponent's move that led to the position, if any
+/// * `fen` - The position itself, so the engine doesn't have to replay the game
+/// * `played_fen` - The position after the move actually played in the game,
+///   `None` outside of a game
+/// * `options` - Tuning knobs for the analysis
+/// * `stockfish` - Mutable reference to a Stockfish engine instance
+///
+/// # Returns
+/// The analysis, or `None` if the game is over in the position
+fn analyze_pos(
+    last_move: usize,
+    previous_mv: Option<&str>,
+    fen: &Fen,
+    played_fen: Option<&Fen>,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Option<PositionData>, GenerationError> {
+    let eval = evaluate(fen, options, stockfish)?;
 
-    let eval = stockfish::eval_pos_moves(&base_moves, stockfish);
+    let mover = fen.as_setup().turn;
 
-    let best_mv = stockfish::best_move_for_pos_moves(&base_moves, 5, stockfish);
-    let full_moves = format!("{base_moves} {best_mv}");
+    // The search score is kept to spot forced mates, which the static
+    // evaluation of the resulting position can't see
+    let (best_mv, search_eval) = match options.time_budget {
+        Some(budget) => {
+            match stockfish::best_move_for_pos_within(fen, options.depth(), budget, stockfish) {
+                Ok(best_mv) => (best_mv, None),
+                Err(StockfishError::GameOver) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        None => match top_lines(fen, options.depth(), 1, options, stockfish)?
+            .into_iter()
+            .next()
+        {
+            Some((best_mv, eval)) => (best_mv, Some(eval)),
+            None => return Ok(None),
+        },
+    };
+    let board = notation::board_of(fen).map_err(|e| GenerationError::EngineError(e.to_string()))?;
+    let forcing = notation::is_forcing(&best_mv, &board)
+        .map_err(|e| GenerationError::EngineError(e.to_string()))?;
+    let recapture = match previous_mv {
+        Some(previous) => notation::is_recapture(&best_mv, previous, &board)
+            .map_err(|e| GenerationError::EngineError(e.to_string()))?,
+        None => false,
+    };
+    let best_fen =
+        fen_after(fen, &best_mv).map_err(|e| GenerationError::EngineError(e.to_string()))?;
 
-    let best_eval = stockfish::eval_pos_moves(&full_moves, stockfish);
+    let best_eval = match search_eval {
+        Some(mate @ Evaluation::Mate(_)) => mate.for_white(mover),
+        _ => evaluate(&best_fen, options, stockfish)?,
+    };
     let delta = compute_delta(&eval, &best_eval);
 
-    PositionData {
+    let played_loss = match played_fen {
+        Some(played_fen) => {
+            let played_eval = evaluate(played_fen, options, stockfish)?;
+            compute_played_loss(mover, &played_eval, &best_eval)
+        }
+        None => 0.0,
+    };
+
+    Ok(Some(PositionData {
         pos: last_move,
         best_mv,
+        eval: eval.pawns(),
         delta,
+        played_loss,
+        forcing,
+        recapture,
+        kind: solution_kind(mover, &best_eval),
+    }))
+}
+
+/// Tells whether the move that led to the position at `move_idx` gave away
+/// at least [`BLUNDER_LOSS`] for the side that played it, both positions
+/// being evaluated by a search whatever the options say
+///
+/// # Arguments
+/// * `fens` - Positions of the game by ply
+/// * `move_idx` - Ply of the position the solver would start from
+fn follows_blunder(
+    fens: &[Fen],
+    move_idx: usize,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<bool, GenerationError> {
+    let Some(before) = move_idx.checked_sub(1).map(|prev| &fens[prev]) else {
+        return Ok(false);
+    };
+    // A piece left hanging hardly moves the static evaluation, only a search
+    // sees it fall
+    let before_eval = evaluate_by(before, true, options, stockfish)?;
+    let after_eval = evaluate_by(&fens[move_idx], true, options, stockfish)?;
+
+    let loss = compute_played_loss(before.as_setup().turn, &after_eval, &before_eval);
+    Ok(loss >= BLUNDER_LOSS)
+}
+
+/// Tells what the best move achieves for the side playing it
+///
+/// # Arguments
+/// * `mover` - Side playing the best move
+/// * `best_eval` - Evaluation after the best move, from white's point of view
+fn solution_kind(mover: Color, best_eval: &Evaluation) -> SolutionKind {
+    // Flipping the sign for black turns white's point of view into the mover's
+    match best_eval.clone().for_white(mover) {
+        Evaluation::Mate(n) if n > 0 => SolutionKind::Mate(u8::try_from(n).unwrap_or(u8::MAX)),
+        Evaluation::Eval(pawns) if pawns.abs() < EQUALITY_MARGIN => SolutionKind::Equalizing,
+        _ => SolutionKind::Material,
     }
 }
 
+/// Evaluates a position from white's point of view, mates being positive
+/// when white mates
+///
+/// Positions are evaluated by a search when asked to, and endgames covered by
+/// tablebases when tablebases are configured, as the static evaluation
+/// neither sees tactics nor probes the tablebases
+fn evaluate(
+    fen: &Fen,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Evaluation, GenerationError> {
+    let by_search = options.search_eval || (options.tablebases && is_endgame(fen));
+    evaluate_by(fen, by_search, options, stockfish)
+}
+
+/// Evaluates a position from white's point of view, by a search or statically
+/// as `by_search` says, going through the cache when there is one
+fn evaluate_by(
+    fen: &Fen,
+    by_search: bool,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Evaluation, GenerationError> {
+    match &options.cache {
+        Some(cache) => cache.evaluation(fen, options.depth(), by_search, || {
+            evaluate_uncached(fen, by_search, options, stockfish)
+        }),
+        None => evaluate_uncached(fen, by_search, options, stockfish),
+    }
+}
+
+fn evaluate_uncached(
+    fen: &Fen,
+    by_search: bool,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Evaluation, GenerationError> {
+    if !by_search {
+        return Ok(stockfish::eval_pos(fen, stockfish)?);
+    }
+
+    let board = notation::board_of(fen).map_err(|e| GenerationError::Internal(e.to_string()))?;
+    if board.is_check() {
+        return Ok(Evaluation::Check);
+    }
+
+    match stockfish::eval_by_search_for_pos(fen, options.depth(), stockfish) {
+        Ok(eval) => Ok(eval),
+        // Stalemate, there's nothing to search
+        Err(StockfishError::GameOver) => Ok(stockfish::eval_pos(fen, stockfish)?),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Best `count` lines of a position, answered from the options' cache when
+/// it has them
+fn top_lines(
+    fen: &Fen,
+    depth: u8,
+    count: usize,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Vec<(String, Evaluation)>, StockfishError> {
+    match &options.cache {
+        Some(cache) => cache.top_lines(fen, depth, count, || {
+            stockfish::top_lines_for_pos(fen, depth, count, stockfish)
+        }),
+        None => stockfish::top_lines_for_pos(fen, depth, count, stockfish),
+    }
+}
+
+fn is_endgame(fen: &Fen) -> bool {
+    fen.as_setup().board.occupied().count() <= TABLEBASE_PIECES
+}
+
+/// Tells whether the best move is clearly better than the second best, by at
+/// least the configured margin, so the solution isn't ambiguous
+fn has_unique_solution(
+    fen: &Fen,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<bool, GenerationError> {
+    let lines = top_lines(fen, options.depth(), 2, options, stockfish)?;
+    let margin = options.unique_margin.unwrap_or(UNIQUE_SOLUTION_GAP);
+    Ok(holds_alone(scores(&lines), margin))
+}
+
+/// Moves of the lines scoring within `margin` pawns of the best line, the
+/// best line's move included
+fn moves_within(lines: &[(String, Evaluation)], margin: f32) -> impl Iterator<Item = &str> {
+    let best = lines.first().and_then(|(_, eval)| eval.pawns());
+    lines
+        .iter()
+        .filter(move |(_, eval)| match (best, eval.pawns()) {
+            (Some(best), Some(eval)) => best - eval < margin,
+            _ => false,
+        })
+        .map(|(mv, _)| mv.as_str())
+}
+
+/// Tells whether the best of the top two lines leads the second by `gap` pawns
+///
+/// # Arguments
+/// * `scores` - Scores of the lines, best line first
+fn holds_alone<'a>(scores: impl IntoIterator<Item = &'a Evaluation>, gap: f32) -> bool {
+    let mut scores = scores.into_iter();
+    match (scores.next(), scores.next()) {
+        (Some(_), None) => true,
+        (Some(best), Some(second)) => match (best.pawns(), second.pawns()) {
+            (Some(best), Some(second)) => best - second >= gap,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn scores(lines: &[(String, Evaluation)]) -> impl Iterator<Item = &Evaluation> {
+    lines.iter().map(|(_, eval)| eval)
+}
+
+/// Picks the best scored candidate along with its solution
+///
+/// Candidates are tried in order, skipping ambiguous ones unless they are
+/// allowed. Mating candidates are solved by the mating line, whatever the
+/// solution length range. Without a range the solution of other candidates is
+/// the single best move, otherwise it is the forced line, which must fit the range
+///
+/// # Arguments
+/// * `candidates` - Candidate positions, best scored first
+/// * `fens` - Positions of the game by ply
+fn pick_candidate(
+    candidates: impl IntoIterator<Item = PositionData>,
+    fens: &[Fen],
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<(PositionData, Vec<String>), GenerationError> {
+    let mut ambiguous = 0;
+    let mut total = 0;
+
+    for candidate in candidates {
+        total += 1;
+        let fen = &fens[candidate.pos];
+        if !options.allow_ambiguous && !has_unique_solution(fen, options, stockfish)? {
+            ambiguous += 1;
+            continue;
+        }
+
+        let solution = match (candidate.kind, &options.solution_plies) {
+            // The mate distance sets how long the solution is
+            (SolutionKind::Mate(mate_in), _) => mating_line(fen, mate_in, options, stockfish)?,
+            (_, None) => vec![candidate.best_mv.clone()],
+            (_, Some(plies)) => {
+                let line = forced_line(fen, *plies.end(), options, stockfish)?;
+                if !plies.contains(&line.len()) {
+                    continue;
+                }
+                line
+            }
+        };
+
+        return Ok((candidate, solution));
+    }
+
+    if total > 0 && ambiguous == total {
+        return Err(GenerationError::NoTacticFound(
+            "no sound puzzle in the game: every candidate position has several good moves"
+                .to_string(),
+        ));
+    }
+    Err(GenerationError::NoTacticFound(
+        "no candidate position matched the generation options".to_string(),
+    ))
+}
+
+/// The engine's mating line, from the solver's first move to the mating one
+///
+/// # Arguments
+/// * `mate_in` - Moves the solver needs to mate
+///
+/// # Returns
+/// The line, ending on a solver move, at most `2 * mate_in - 1` plies long
+fn mating_line(
+    fen: &Fen,
+    mate_in: u8,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Vec<String>, GenerationError> {
+    let mut line = match stockfish::analyze_for_pos(fen, options.depth(), stockfish) {
+        Ok(analysis) => analysis.pv,
+        Err(StockfishError::GameOver) => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    line.truncate(2 * usize::from(mate_in) - 1);
+    if line.len().is_multiple_of(2) {
+        line.pop();
+    }
+
+    Ok(line)
+}
+
+/// Follows the engine's main line from a position where the solver's move is
+/// the only one that holds
+///
+/// Every solver move must stand alone: a two line search of each position the
+/// solver is to move in checks that, its main line giving the solver's move
+/// and the reply to it. The reply's position is searched only when that main
+/// line stops at the solver's move.
+///
+/// # Returns
+/// The forced line, ending on a solver move, at most `max_plies` long
+fn forced_line(
+    fen: &Fen,
+    max_plies: usize,
+    options: &GenerationOptions,
+    stockfish: &mut Stockfish,
+) -> Result<Vec<String>, GenerationError> {
+    let mut line = Vec::new();
+    let mut fen = fen.clone();
+
+    while line.len() < max_plies {
+        let variations = stockfish::top_variations_for_pos(&fen, options.depth(), 2, stockfish)?;
+        if !holds_alone(variations.iter().map(|variation| &variation.score), ONLY_MOVE_GAP) {
+            break;
+        }
+        let Some(best) = variations.into_iter().next() else {
+            break;
+        };
+        let mut main_line = best.pv.into_iter();
+        let Some(solver_move) = main_line.next() else {
+            break;
+        };
+        play_along(&mut fen, &mut line, solver_move)?;
+        if line.len() == max_plies {
+            break;
+        }
+
+        let reply = match main_line.next() {
+            Some(reply) => reply,
+            None => match stockfish::analyze_for_pos(&fen, options.depth(), stockfish) {
+                Ok(analysis) => analysis.pv[0].clone(),
+                Err(StockfishError::GameOver) => break,
+                Err(e) => return Err(e.into()),
+            },
+        };
+        play_along(&mut fen, &mut line, reply)?;
+    }
+
+    if line.len().is_multiple_of(2) {
+        line.pop();
+    }
+
+    Ok(line)
+}
+
+/// Replays the game from its start position, collecting the FEN before every
+/// move and after the last one
+fn fens_by_ply(mut board: Chess, pgn: &Pgn) -> Result<Vec<Fen>, InvalidNotationError> {
+    let mut fens = vec![notation::fen_of(&board)];
+
+    for mov in pgn.moves() {
+        notation::play_uci(mov, &mut board)?;
+        fens.push(notation::fen_of(&board));
+    }
+
+    Ok(fens)
+}
+
+/// Plays a move of the engine's line, appending it to `line`
+fn play_along(fen: &mut Fen, line: &mut Vec<String>, mv: String) -> Result<(), GenerationError> {
+    *fen = fen_after(fen, &mv).map_err(|e| GenerationError::EngineError(e.to_string()))?;
+    line.push(mv);
+    Ok(())
+}
+
+fn fen_after(fen: &Fen, uci: &str) -> Result<Fen, NotationError> {
+    let mut board = notation::board_of(fen)?;
+    notation::play_uci(uci, &mut board)?;
+    Ok(notation::fen_of(&board))
+}
+
 /// Computes the absolute difference between two position evaluations
 ///
 /// # Arguments
-/// * `pos_eval` - Evaluation of the current position
-/// * `best_move_eval` - Evaluation after the best move
+/// * `pos_eval` - Evaluation of the current position, from white's point of view
+/// * `best_move_eval` - Evaluation after the best move, from white's point of view
 ///
 /// # Returns
-/// The absolute difference between evaluations
+/// The absolute difference between evaluations, infinite when a mate appears
+/// or changes hands
 fn compute_delta(pos_eval: &Evaluation, best_move_eval: &Evaluation) -> f32 {
     match (pos_eval, best_move_eval) {
+        // A mate that was already there and still is isn't a swing
+        (Evaluation::Mate(pos_mate), Evaluation::Mate(best_mate))
+            if (*pos_mate > 0) == (*best_mate > 0) =>
+        {
+            0.0
+        }
+        (Evaluation::Mate(_), _) | (_, Evaluation::Mate(_)) => f32::INFINITY,
+
         // If both are numerical evaluations, return absolute difference
         (Evaluation::Eval(pos_val), Evaluation::Eval(best_val)) => (pos_val - best_val).abs(),
 
@@ -156,19 +1541,648 @@ fn compute_delta(pos_eval: &Evaluation, best_move_eval: &Evaluation) -> f32 {
     }
 }
 
+/// Computes how much eval the mover gave away by not playing the best move
+///
+/// # Arguments
+/// * `mover` - Side that played the move
+/// * `played_eval` - Evaluation after the move played in the game
+/// * `best_move_eval` - Evaluation after the best move
+///
+/// # Returns
+/// The loss from the mover's point of view, negative if the played move was
+/// rated better than the engine's choice and zero when either side is in check
+fn compute_played_loss(mover: Color, played_eval: &Evaluation, best_move_eval: &Evaluation) -> f32 {
+    match (played_eval.pawns(), best_move_eval.pawns()) {
+        // Evaluations favor white when positive, so flip them for black
+        (Some(played_val), Some(best_val)) => mover.fold_wb(1.0, -1.0) * (best_val - played_val),
+
+        // A position in check has no static evaluation to compare against
+        (_, _) => 0.0,
+    }
+}
+
+/// Range of plies candidates are taken from: the window of the options when
+/// one is set, a random range otherwise
+///
+/// # Returns
+/// The range, or an error if the window is empty or goes past the last ply
+fn candidate_range(
+    moves: &Pgn,
+    options: &GenerationOptions,
+    rng: &mut impl Rng,
+) -> Result<RangeInclusive<usize>, GenerationError> {
+    if options.from_ply.is_none() && options.to_ply.is_none() {
+        return rand_range_of_moves(moves, rng);
+    }
+
+    let last = moves.moves().len() - 1;
+    let from = options.from_ply.unwrap_or(0);
+    let to = options.to_ply.unwrap_or(last);
+    if to > last {
+        return Err(GenerationError::InvalidWindow(format!(
+            "ply window ends at ply {to}, past the last ply {last} of the game"
+        )));
+    }
+    if from > to {
+        return Err(GenerationError::InvalidWindow(format!(
+            "ply window {from}..={to} is empty"
+        )));
+    }
+
+    Ok(from..=to)
+}
+
 /// Generates a random range of moves to analyze
 ///
 /// # Arguments
 /// * `moves` - Total sequence of moves
+/// * `rng` - Source of randomness for the end of the range
 ///
 /// # Returns
-/// A tuple containing the start and end indices of the range
-fn rand_range_of_moves(moves: &Pgn) -> RangeInclusive<usize> {
+/// A tuple containing the start and end indices of the range, or an error
+/// when the game is too short to sample a range from
+fn rand_range_of_moves(
+    moves: &Pgn,
+    rng: &mut impl Rng,
+) -> Result<RangeInclusive<usize>, GenerationError> {
+    let len = moves.moves().len();
     // Start from one-third of the way through the moves
-    let from: usize = moves.moves().len() / 3;
+    let from: usize = len / 3;
+
+    // End at a random point between start+1 and the end, leaving the last
+    // move so every candidate has a played move to compare against
+    let end = len.saturating_sub(1);
+    if from + 1 >= end {
+        return Err(GenerationError::GameTooShort(len));
+    }
+    let to: usize = rng.random_range(from + 1..end);
+
+    Ok(from..=to)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::domain::pgn::read_pgns_str;
+    use crate::domain::stockfish::tests::fake_engine;
+
+    /// White mates in two along the back rank: 1. Re8+ Rxe8 2. Rxe8#
+    pub(crate) const MATE_IN_TWO: &str = "r5k1/5ppp/8/8/8/8/4RPPP/4R1K1 w - - 0 1";
+
+    fn candidate(best_mv: &str, kind: SolutionKind) -> PositionData {
+        PositionData {
+            pos: 0,
+            best_mv: best_mv.to_string(),
+            eval: None,
+            delta: f32::INFINITY,
+            played_loss: 0.0,
+            forcing: true,
+            recapture: false,
+            kind,
+        }
+    }
+
+    #[test]
+    fn epd_lists_every_acceptable_best_move() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let mut puzzle = Puzzle::from_fen_and_moves(start, "e2e4").unwrap();
+        assert_eq!(
+            puzzle.to_epd(1).unwrap(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4; id \"puzzle 1\";"
+        );
+
+        puzzle.alternative_moves = vec![Move::from_str("d2d4").ok().unwrap()];
+        assert_eq!(
+            puzzle.to_epd(2).unwrap(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4 d4; id \"puzzle 2\";"
+        );
+    }
+
+    #[test]
+    fn moves_within_the_margin_are_acceptable() {
+        let lines = [
+            ("e2e4".to_string(), Evaluation::Eval(0.4)),
+            ("d2d4".to_string(), Evaluation::Eval(0.3)),
+            ("g1f3".to_string(), Evaluation::Eval(-1.5)),
+        ];
+        assert_eq!(moves_within(&lines, 1.0).collect::<Vec<_>>(), ["e2e4", "d2d4"]);
+        assert_eq!(moves_within(&lines, 0.05).collect::<Vec<_>>(), ["e2e4"]);
+
+        let mates = [
+            ("h5f7".to_string(), Evaluation::Mate(1)),
+            ("d1h5".to_string(), Evaluation::Mate(3)),
+            ("e2e4".to_string(), Evaluation::Check),
+        ];
+        assert_eq!(moves_within(&mates, 1.5).collect::<Vec<_>>(), ["h5f7"]);
+        assert_eq!(moves_within(&mates, 3.0).collect::<Vec<_>>(), ["h5f7", "d1h5"]);
+    }
+
+    /// Fake engine answering its searches in turn with the given `info`
+    /// lines and a best move, and reporting the game over past the last one
+    fn scripted_searches(searches: &[&str]) -> Stockfish {
+        let arms: String = searches
+            .iter()
+            .enumerate()
+            .map(|(n, info)| format!("\n        {}) {info};;", n + 1))
+            .collect();
+        fake_engine(&format!(
+            r#"
+    go*) n=$((n+1)); case $n in{arms}
+        *) echo "bestmove (none)";;
+        esac;;"#
+        ))
+    }
+
+    #[test]
+    fn forced_line_takes_the_reply_from_the_main_line() {
+        let mut stockfish = scripted_searches(&[
+            r#"echo "info depth 8 multipv 1 score cp 900 pv e2e8 a8e8 e1e8"; echo "bestmove e2e8""#,
+            r#"echo "info depth 8 multipv 1 score mate 1 pv e1e8"; echo "bestmove e1e8""#,
+        ]);
+        let fen = MATE_IN_TWO.parse().unwrap();
+        let options = GenerationOptions::default();
+
+        let line = forced_line(&fen, 3, &options, &mut stockfish).unwrap();
+        assert_eq!(line, ["e2e8", "a8e8", "e1e8"]);
+    }
+
+    #[test]
+    fn forced_line_searches_the_reply_past_a_short_main_line() {
+        let mut stockfish = scripted_searches(&[
+            r#"echo "info depth 8 multipv 1 score cp 900 pv e2e8"; echo "bestmove e2e8""#,
+            r#"echo "info depth 8 score mate -1 pv a8e8 e1e8"; echo "bestmove a8e8""#,
+            r#"echo "info depth 8 multipv 1 score mate 1 pv e1e8"; echo "bestmove e1e8""#,
+        ]);
+        let fen = MATE_IN_TWO.parse().unwrap();
+        let options = GenerationOptions::default();
+
+        let line = forced_line(&fen, 3, &options, &mut stockfish).unwrap();
+        assert_eq!(line, ["e2e8", "a8e8", "e1e8"]);
+    }
+
+    #[test]
+    fn forced_line_stops_at_a_later_move_that_isnt_the_only_one() {
+        let mut stockfish = scripted_searches(&[
+            r#"echo "info depth 8 multipv 1 score cp 900 pv e2e8 a8e8 e1e8"; echo "bestmove e2e8""#,
+            concat!(
+                r#"echo "info depth 8 multipv 1 score cp 900 pv e1e8"; "#,
+                r#"echo "info depth 8 multipv 2 score cp 880 pv f2f3"; echo "bestmove e1e8""#
+            ),
+        ]);
+        let fen = MATE_IN_TWO.parse().unwrap();
+        let options = GenerationOptions::default();
+
+        let line = forced_line(&fen, 3, &options, &mut stockfish).unwrap();
+        assert_eq!(line, ["e2e8"]);
+    }
+
+    #[test]
+    fn forced_line_stops_when_the_move_isnt_the_only_one() {
+        let mut stockfish = scripted_searches(&[concat!(
+            r#"echo "info depth 8 multipv 1 score cp 900 pv e2e8"; "#,
+            r#"echo "info depth 8 multipv 2 score cp 850 pv e2e7"; echo "bestmove e2e8""#
+        )]);
+        let fen = MATE_IN_TWO.parse().unwrap();
+        let options = GenerationOptions::default();
+
+        let line = forced_line(&fen, 3, &options, &mut stockfish).unwrap();
+        assert!(line.is_empty());
+    }
+
+    #[test]
+    fn levels_set_the_length_of_the_forced_line() {
+        // Kings shuffling between the corner and g-file, each white move
+        // being the only one and the reply coming with it
+        let mut stockfish = fake_engine(
+            r#"
+    go*)
+      case "$pos" in
+        *"4R1K1 w"*) echo "info depth 8 multipv 1 score cp 900 pv g1h1 g8h8";;
+        *"4R2K w"*) echo "info depth 8 multipv 1 score cp 900 pv h1g1 h8g8";;
+      esac
+      echo "bestmove a2a3";;"#,
+        );
+        let fen: Fen = MATE_IN_TWO.parse().unwrap();
+
+        for (level, plies) in [
+            (PuzzleLevel::Easy, 1),
+            (PuzzleLevel::Medium, 3),
+            (PuzzleLevel::Hard, 5),
+        ] {
+            let options = GenerationOptions::for_level(level);
+            assert_eq!(options.solution_plies, Some(plies..=plies));
+
+            let line = forced_line(&fen, plies, &options, &mut stockfish).unwrap();
+            assert_eq!(line.len(), plies, "{level:?}: {line:?}");
+        }
+    }
+
+    #[test]
+    fn mate_in_two_is_solved_to_the_mate_at_any_level() {
+        let mut stockfish = fake_engine(
+            r#"
+    go*) echo "info depth 8 score mate 2 pv e2e8 a8e8 e1e8"; echo "bestmove e2e8";;"#,
+        );
+        let fens = vec![MATE_IN_TWO.parse().unwrap()];
+
+        for level in [PuzzleLevel::Easy, PuzzleLevel::Medium, PuzzleLevel::Hard] {
+            let options = GenerationOptions {
+                allow_ambiguous: true,
+                ..GenerationOptions::for_level(level)
+            };
+            let (picked, solution) = pick_candidate(
+                [candidate("e2e8", SolutionKind::Mate(2))],
+                &fens,
+                &options,
+                &mut stockfish,
+            )
+            .unwrap();
+
+            assert_eq!(picked.kind, SolutionKind::Mate(2));
+            assert_eq!(solution, ["e2e8", "a8e8", "e1e8"], "{level:?}");
+        }
+    }
+
+    /// Fake engine that sees the mate in two of [`MATE_IN_TWO`] in whatever
+    /// it is asked, and evaluates every position as slightly better for white
+    pub(crate) fn mate_in_two_engine() -> Stockfish {
+        fake_engine(
+            r#"
+    eval) echo "Final evaluation       0.5 (white side)";;
+    go*) echo "info depth 8 multipv 1 score mate 2 pv e2e8 a8e8 e1e8"; echo "bestmove e2e8";;"#,
+        )
+    }
+
+    #[test]
+    fn mate_is_found_from_a_position() {
+        let mut stockfish = mate_in_two_engine();
+        let options = GenerationOptions::for_level(PuzzleLevel::Hard);
+
+        let puzzle = generate_puzzle_from_fen(MATE_IN_TWO, &options, &mut stockfish).unwrap();
+        assert_eq!(puzzle.fen, MATE_IN_TWO);
+        assert_eq!(puzzle.solution_uci(), "e2e8 a8e8 e1e8");
+        assert_eq!(puzzle.solution, puzzle.moves[puzzle.start_pos..]);
+        assert_eq!(puzzle.solution_kind, SolutionKind::Mate(2));
+        assert_eq!(puzzle.level, Some(PuzzleLevel::Hard));
+        assert!(puzzle.only_move);
+        assert!(puzzle.themes.contains(&PuzzleTheme::BackRankMate));
+        assert!(puzzle.to_epd(1).unwrap().contains(" bm Re8+;"));
+
+        let json = serde_json::to_value(&puzzle).unwrap();
+        assert_eq!(json["startFen"], MATE_IN_TWO);
+        assert_eq!(json["solutionKind"], serde_json::json!({ "mate": 2 }));
+    }
+
+    #[test]
+    fn solution_is_the_line_the_level_asked_for() {
+        // The engine's main line goes on checking well past one solver move
+        let mut stockfish = fake_engine(
+            r#"
+    eval) echo "Final evaluation       0.5 (white side)";;
+    go*)
+      echo "info depth 8 multipv 1 score cp 500 pv a1a8 h8h7 a8a7 h7h8 a7a8"
+      echo "bestmove a1a8";;"#,
+        );
+        let options = GenerationOptions::for_level(PuzzleLevel::Easy);
 
-    // End at a random point between start+1 and the end
-    let to: usize = rand::random_range(from + 1..moves.moves().len() - 1);
+        let fen = "7k/8/8/8/8/8/8/R5K1 w - - 0 1";
+        let puzzle = generate_puzzle_from_fen(fen, &options, &mut stockfish).unwrap();
+        assert_eq!(puzzle.solution_uci(), "a1a8");
+        assert_eq!(puzzle.solution, puzzle.moves[puzzle.start_pos..]);
+    }
+
+    #[test]
+    fn ambiguous_candidates_are_rejected() {
+        let mut stockfish = fake_engine(
+            r#"
+    go*)
+      echo "info depth 8 multipv 1 score cp 30 pv e2e4"
+      echo "info depth 8 multipv 2 score cp 25 pv d2d4"
+      echo "bestmove e2e4";;"#,
+        );
+        let fens = vec![notation::fen_of(&Chess::default())];
+        let options = GenerationOptions::default();
+
+        let picked = pick_candidate(
+            [candidate("e2e4", SolutionKind::Material)],
+            &fens,
+            &options,
+            &mut stockfish,
+        );
+        let Err(GenerationError::NoTacticFound(reason)) = picked else {
+            panic!("an ambiguous position made a puzzle");
+        };
+        assert!(reason.contains("several good moves"), "{reason}");
+    }
+
+    #[test]
+    fn games_too_short_to_sample_are_rejected() {
+        let mut stockfish = fake_engine("");
+        let options = GenerationOptions::default();
+
+        let outcome =
+            generate_puzzle_by_position_analysis("e2e4 e7e5 g1f3", &options, &mut stockfish);
+        assert!(matches!(outcome, Err(GenerationError::GameTooShort(3))));
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let three: Pgn = "e2e4 e7e5 g1f3".parse().unwrap();
+        assert!(matches!(
+            rand_range_of_moves(&three, &mut rng),
+            Err(GenerationError::GameTooShort(3))
+        ));
+
+        let six: Pgn = "e2e4 e7e5 g1f3 b8c6 f1c4 g8f6".parse().unwrap();
+        for _ in 0..50 {
+            let range = rand_range_of_moves(&six, &mut rng).unwrap();
+            assert_eq!(*range.start(), 2);
+            assert!((3..5).contains(range.end()), "{range:?}");
+        }
+    }
+
+    #[test]
+    fn seed_makes_the_puzzle_reproducible() {
+        // Knights going back and forth, so b1c3 and b8c6 are always legal,
+        // and evaluations growing with the halfmove clock, so the latest
+        // candidate of the random range makes the puzzle
+        let mut stockfish = fake_engine(
+            r#"
+    eval)
+      set -- $pos
+      echo "Final evaluation       $(( $7 * $7 )).0 (white side)";;
+    go*)
+      case "$pos" in
+        *" w "*) echo "info depth 8 multipv 1 score cp 50 pv b1c3";;
+        *) echo "info depth 8 multipv 1 score cp 50 pv b8c6";;
+      esac
+      echo "bestmove 0000";;"#,
+        );
+        let moves = "g1f3 g8f6 f3g1 f6g8 ".repeat(5);
+        let options = GenerationOptions {
+            seed: Some(42),
+            ..GenerationOptions::default()
+        };
+
+        let puzzles: Vec<_> = (0..2)
+            .map(|_| {
+                let puzzle =
+                    generate_puzzle_by_position_analysis(&moves, &options, &mut stockfish);
+                serde_json::to_value(puzzle.unwrap()).unwrap()
+            })
+            .collect();
+        assert_eq!(puzzles[0], puzzles[1]);
+    }
+
+    #[test]
+    fn window_bounds_the_candidates() {
+        let game: Pgn = read_first_sample_game().parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let window = |from_ply, to_ply| GenerationOptions {
+            from_ply,
+            to_ply,
+            ..GenerationOptions::default()
+        };
+
+        let range = candidate_range(&game, &window(Some(10), Some(14)), &mut rng).unwrap();
+        assert_eq!(range, 10..=14);
+        let last = game.moves().len() - 1;
+        let range = candidate_range(&game, &window(Some(20), None), &mut rng).unwrap();
+        assert_eq!(range, 20..=last);
+
+        let past_the_end = candidate_range(&game, &window(None, Some(last + 1)), &mut rng);
+        assert!(matches!(past_the_end, Err(GenerationError::InvalidWindow(_))));
+        let empty = candidate_range(&game, &window(Some(14), Some(10)), &mut rng);
+        assert!(matches!(empty, Err(GenerationError::InvalidWindow(_))));
+    }
+
+    /// Moves of the first game of the sample database, Morphy's Opera game
+    fn read_first_sample_game() -> String {
+        let sample = include_str!("../../benches/fixtures/sample.pgn");
+        read_pgns_str(sample)
+            .remove(0)
+            .unwrap()
+            .movetext()
+    }
+
+    #[test]
+    fn blunders_lose_for_the_side_that_played_them() {
+        // Evaluations are from white's point of view, the loss from the mover's
+        let loss = |mover, played, best| compute_played_loss(mover, &played, &best);
+        assert_eq!(loss(Color::White, Evaluation::Eval(-2.0), Evaluation::Eval(1.0)), 3.0);
+        assert_eq!(loss(Color::Black, Evaluation::Eval(2.0), Evaluation::Eval(-1.0)), 3.0);
+        assert_eq!(loss(Color::White, Evaluation::Check, Evaluation::Eval(1.0)), 0.0);
+
+        assert_eq!(compute_delta(&Evaluation::Eval(0.5), &Evaluation::Eval(-1.5)), 2.0);
+        assert!(compute_delta(&Evaluation::Eval(0.5), &Evaluation::Mate(3)).is_infinite());
+        assert!(compute_delta(&Evaluation::Mate(-2), &Evaluation::Mate(4)).is_infinite());
+        assert_eq!(compute_delta(&Evaluation::Mate(5), &Evaluation::Mate(4)), 0.0);
+    }
+
+    #[test]
+    fn positions_after_a_blunder_are_spotted() {
+        // White is fine before 1. e4 and lost from then on, scores being given
+        // for the side to move
+        let mut stockfish = fake_engine(
+            r#"
+    go*)
+      case "$pos" in
+        *" b "*) echo "info depth 8 multipv 1 score cp 300 pv e7e5";;
+        *4p3*) echo "info depth 8 multipv 1 score cp -300 pv g1f3";;
+        *) echo "info depth 8 multipv 1 score cp 20 pv e2e4";;
+      esac
+      echo "bestmove 0000";;"#,
+        );
+        let start = Chess::default();
+        let fens = fens_by_ply(start, &"e2e4 e7e5".parse().unwrap()).unwrap();
+        let options = GenerationOptions {
+            mode: GenerationMode::PunishBlunder,
+            ..GenerationOptions::default()
+        };
+
+        assert!(follows_blunder(&fens, 1, &options, &mut stockfish).unwrap());
+        // Black's move gave nothing away, and the first position follows no move
+        assert!(!follows_blunder(&fens, 2, &options, &mut stockfish).unwrap());
+        assert!(!follows_blunder(&fens, 0, &options, &mut stockfish).unwrap());
+    }
+
+    #[test]
+    fn hanging_queen_is_taken_back() {
+        // 4... Qg5?? leaves the queen to the knight on f3, which the static
+        // evaluation can't see before the knight has taken it
+        let mut stockfish = fake_engine(
+            r#"
+    eval)
+      case "$pos" in
+        *2b1p1N1*) echo "Final evaluation       9.0 (white side)";;
+        *) echo "Final evaluation       0.2 (white side)";;
+      esac;;
+    go*)
+      case "$pos" in
+        *2b1p1q1*) echo "info depth 8 multipv 1 score cp 900 pv f3g5 g8f6";;
+        *2b1p1N1*) echo "info depth 8 multipv 1 score cp -900 pv g8f6";;
+        *) echo "info depth 8 multipv 1 score cp 20 pv a7a6";;
+      esac
+      echo "bestmove 0000";;"#,
+        );
+        let options = GenerationOptions {
+            mode: GenerationMode::PunishBlunder,
+            from_ply: Some(6),
+            to_ply: Some(9),
+            ..GenerationOptions::default()
+        };
 
-    from..=to
+        let puzzle = generate_puzzle_by_position_analysis(
+            "e2e4 e7e5 g1f3 b8c6 f1c4 f8c5 b1c3 d8g5 f3g5 g8f6 d2d3",
+            &options,
+            &mut stockfish,
+        )
+        .unwrap();
+        assert_eq!(puzzle.start_pos, 8);
+        assert_eq!(puzzle.solution.len(), 1);
+        assert_eq!(puzzle.solution[0].to_string(), "f3g5");
+    }
+
+    #[test]
+    fn recaptures_are_passed_over() {
+        // 2... Qxd5 wins the pawn back, more than 3. Nc3 gains for white, but
+        // taking back is too obvious to make a puzzle
+        let mut stockfish = fake_engine(
+            r#"
+    eval)
+      case "$pos" in
+        *"/3P4/"*) echo "Final evaluation       1.0 (white side)";;
+        *"/2N5/"*) echo "Final evaluation       0.5 (white side)";;
+        *) echo "Final evaluation       0.0 (white side)";;
+      esac;;
+    go*)
+      case "$pos" in
+        *"/3P4/"*" b "*) echo "info depth 8 multipv 1 score cp 0 pv d8d5";;
+        *"/3q4/8/8/"*" w "*) echo "info depth 8 multipv 1 score cp 50 pv b1c3";;
+      esac
+      echo "bestmove 0000";;"#,
+        );
+        let moves = "e2e4 d7d5 e4d5 d8d5 b1c3 d5a5 d2d4 g8f6";
+        let options = GenerationOptions {
+            from_ply: Some(3),
+            to_ply: Some(4),
+            ..GenerationOptions::default()
+        };
+
+        let puzzle = generate_puzzle_by_position_analysis(moves, &options, &mut stockfish);
+        assert_eq!(puzzle.unwrap().solution_uci(), "b1c3");
+
+        let options = GenerationOptions {
+            allow_recaptures: true,
+            ..options
+        };
+        let puzzle = generate_puzzle_by_position_analysis(moves, &options, &mut stockfish);
+        assert_eq!(puzzle.unwrap().solution_uci(), "d8d5");
+    }
+
+    #[test]
+    fn puzzles_keep_their_distance() {
+        assert!(too_close(&(10..=13), 10));
+        assert!(too_close(&(10..=13), 16));
+        assert!(too_close(&(10..=13), 7));
+        assert!(!too_close(&(10..=13), 17));
+        assert!(!too_close(&(10..=13), 6));
+    }
+
+    #[test]
+    fn tactics_of_one_game_make_separate_puzzles() {
+        // The Opera game with 7. Qb3, hitting b7 and f7, and the mate in two
+        // 16. Qb8+ Nxb8 17. Rd8#, the engine seeing nothing elsewhere
+        let mut stockfish = fake_engine(
+            r#"
+    eval)
+      case "$pos" in
+        *"/1Q6/PPP2PPP/RNB1K2R b"*) echo "Final evaluation       3.0 (white side)";;
+        *) echo "Final evaluation       0.0 (white side)";;
+      esac;;
+    go*)
+      case "$pos" in
+        *"/5Q2/PPP2PPP/RNB1K2R w"*) echo "info depth 8 multipv 1 score cp 300 pv f3b3 d8e7";;
+        *"4kb1r/p2n1ppp/"*" w "*) echo "info depth 8 multipv 1 score mate 2 pv b3b8 d7b8 d1d8";;
+      esac
+      echo "bestmove 0000";;"#,
+        );
+        let options = GenerationOptions {
+            from_ply: Some(0),
+            ..GenerationOptions::default()
+        };
+
+        let puzzles =
+            generate_puzzles(&read_first_sample_game(), 3, &options, &mut stockfish).unwrap();
+        let starts: Vec<_> = puzzles.iter().map(|puzzle| puzzle.start_pos).collect();
+        assert_eq!(starts, [30, 12]);
+        let spans: Vec<_> = puzzles
+            .iter()
+            .map(|puzzle| puzzle.start_pos..=puzzle.start_pos + puzzle.solution.len())
+            .collect();
+        for (i, span) in spans.iter().enumerate() {
+            for other in &spans[i + 1..] {
+                assert!(span.end() < other.start() || other.end() < span.start());
+            }
+        }
+        let fens: HashSet<_> = puzzles.iter().map(|puzzle| &puzzle.fen).collect();
+        assert_eq!(fens.len(), puzzles.len());
+    }
+
+    #[test]
+    fn levels_parse_from_names_initials_and_numbers() {
+        for (spellings, level) in [
+            (["easy", "E", "1", " Easy "], PuzzleLevel::Easy),
+            (["medium", "m", "2", "MEDIUM"], PuzzleLevel::Medium),
+            (["hard", "h", "3", "Hard"], PuzzleLevel::Hard),
+        ] {
+            for spelling in spellings {
+                assert_eq!(spelling.parse::<PuzzleLevel>().unwrap(), level, "{spelling}");
+            }
+        }
+
+        let Err(InvalidLevelError(message)) = "expert".parse::<PuzzleLevel>() else {
+            panic!("expert isn't a level");
+        };
+        assert!(message.contains("easy/medium/hard"), "{message}");
+    }
+
+    #[test]
+    fn moves_stay_on_the_board() {
+        for valid in ["e2e4", "e7e8q", "a1h8", "b2b1n"] {
+            assert_eq!(Move::from_str(valid).ok().unwrap().to_string(), valid);
+        }
+        for invalid in ["e7e8z", "i2i4", "e9e1", "a0a2", "e2e", "e7e8qq", "é2e4"] {
+            assert!(Move::from_str(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn pgn_export_replays_the_setup_moves() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let mut puzzle = Puzzle::from_fen_and_moves(start, "e2e4 e7e5 g1f3 b8c6").unwrap();
+        puzzle.start_pos = 2;
+
+        let pgn = puzzle.to_pgn().unwrap();
+        assert!(pgn.contains("1. e4 e5 { Solution: 2. Nf3 Nc6 } *"), "{pgn}");
+
+        let games = read_pgns_str(&pgn);
+        let game = games[0].as_ref().unwrap();
+        assert_eq!(game.moves, ["e2e4", "e7e5"]);
+        assert_eq!(game.start_fen.as_deref(), Some(start));
+    }
+
+    #[test]
+    fn lichess_row_reads_back_into_the_puzzle() {
+        let puzzle = Puzzle::from_fen_and_moves(MATE_IN_TWO, "e2e8 a8e8 e1e8").unwrap();
+        let row = puzzle.to_lichess_csv_row(7).unwrap();
+
+        let columns: Vec<&str> = row.split(',').collect();
+        assert_eq!(columns.len(), LICHESS_CSV_HEADER.split(',').count());
+        assert_eq!(columns[0], "7");
+        assert_eq!(columns[3], puzzle.rating.to_string());
+        assert!(columns[7].split(' ').any(|theme| theme == "mateIn2"), "{row}");
+
+        let read_back = Puzzle::from_fen_and_moves(columns[1], columns[2]).unwrap();
+        assert_eq!(read_back.start_fen().unwrap(), puzzle.start_fen().unwrap());
+        assert_eq!(read_back.solution_uci(), puzzle.solution_uci());
+    }
 }
@@ -0,0 +1,177 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::iter::FromIterator;
+
+use serde::{Deserialize, Serialize};
+use shakmaty::Color;
+
+use super::puzzle::{LICHESS_CSV_HEADER, Puzzle};
+use super::themes::PuzzleTheme;
+
+/// A curated collection of puzzles, serialized as a plain JSON array
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PuzzleSet(Vec<Puzzle>);
+
+impl PuzzleSet {
+    pub fn puzzles(&self) -> &Vec<Puzzle> {
+        &self.0
+    }
+
+    pub fn into_puzzles(self) -> Vec<Puzzle> {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Keeps only puzzles where `side` is the one to find the solution
+    pub fn filter_by_side(self, side: Color) -> Self {
+        self.0
+            .into_iter()
+            .filter(|puzzle| puzzle.side_to_move() == side)
+            .collect()
+    }
+
+    /// Keeps only puzzles whose solution features `theme`
+    pub fn filter_by_theme(self, theme: PuzzleTheme) -> Self {
+        self.0
+            .into_iter()
+            .filter(|puzzle| puzzle.themes.contains(&theme))
+            .collect()
+    }
+
+    /// Orders the puzzles from the lowest rated to the highest, puzzles of
+    /// the same rating keeping their order
+    pub fn sort_by_rating(mut self) -> Self {
+        self.0.sort_by_key(|puzzle| puzzle.rating);
+        self
+    }
+
+    /// Removes puzzles starting from a position already present in the set,
+    /// keeping the first occurrence
+    ///
+    /// Positions are compared by their normalized key, so transpositions and
+    /// differing move clocks still count as the same position
+    pub fn dedup(self) -> Self {
+        let mut seen = HashSet::new();
+
+        self.0
+            .into_iter()
+            .filter(|puzzle| match puzzle.position_key() {
+                Ok(key) => seen.insert(key),
+                Err(_) => true,
+            })
+            .collect()
+    }
+
+    /// Writes the set in the Lichess puzzle CSV format, a header followed by
+    /// one row per puzzle numbered from 1
+    pub fn write_lichess_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "{LICHESS_CSV_HEADER}")?;
+        for (idx, puzzle) in self.0.iter().enumerate() {
+            let row = puzzle
+                .to_lichess_csv_row(idx + 1)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            writeln!(writer, "{row}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Vec<Puzzle>> for PuzzleSet {
+    fn from(puzzles: Vec<Puzzle>) -> Self {
+        PuzzleSet(puzzles)
+    }
+}
+
+impl FromIterator<Puzzle> for PuzzleSet {
+    fn from_iter<I: IntoIterator<Item = Puzzle>>(iter: I) -> Self {
+        PuzzleSet(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// White mates on the back rank with Ra8#
+    const BACK_RANK: &str = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+
+    /// Black mates on the back rank with Ra1#
+    const BLACK_BACK_RANK: &str = "r5k1/8/8/8/8/8/5PPP/6K1 b - - 0 1";
+
+    fn puzzle(fen: &str, moves: &str, rating: u32, themes: Vec<PuzzleTheme>) -> Puzzle {
+        let mut puzzle = Puzzle::from_fen_and_moves(fen, moves).unwrap();
+        puzzle.rating = rating;
+        puzzle.themes = themes;
+        puzzle
+    }
+
+    fn sample_set() -> PuzzleSet {
+        PuzzleSet::from(vec![
+            puzzle(BACK_RANK, "a1a8", 1500, vec![PuzzleTheme::BackRankMate]),
+            puzzle(
+                BLACK_BACK_RANK,
+                "a8a1",
+                900,
+                vec![PuzzleTheme::BackRankMate],
+            ),
+            puzzle(BACK_RANK, "a1a8", 1200, vec![PuzzleTheme::Fork]),
+        ])
+    }
+
+    #[test]
+    fn sorts_by_rating() {
+        let ratings: Vec<u32> = sample_set()
+            .sort_by_rating()
+            .puzzles()
+            .iter()
+            .map(|puzzle| puzzle.rating)
+            .collect();
+        assert_eq!(ratings, [900, 1200, 1500]);
+    }
+
+    #[test]
+    fn filters_by_theme() {
+        let forks = sample_set().filter_by_theme(PuzzleTheme::Fork);
+        assert_eq!(forks.len(), 1);
+        assert_eq!(forks.puzzles()[0].rating, 1200);
+        assert!(sample_set().filter_by_theme(PuzzleTheme::Skewer).is_empty());
+    }
+
+    #[test]
+    fn filters_by_side() {
+        assert_eq!(sample_set().filter_by_side(Color::White).len(), 2);
+        assert_eq!(sample_set().filter_by_side(Color::Black).len(), 1);
+    }
+
+    #[test]
+    fn dedup_keeps_the_first_of_each_position() {
+        let set = sample_set().dedup();
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.puzzles()[0].rating, 1500);
+    }
+
+    #[test]
+    fn writes_one_csv_row_per_puzzle() {
+        let mut csv = Vec::new();
+        sample_set().write_lichess_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], LICHESS_CSV_HEADER);
+    }
+
+    #[test]
+    fn serializes_as_a_plain_array() {
+        let json = serde_json::to_value(sample_set()).unwrap();
+        assert_eq!(json.as_array().map(Vec::len), Some(3));
+    }
+}
@@ -0,0 +1,98 @@
+use std::ops::RangeInclusive;
+
+/// Ratings a puzzle can be given
+///
+/// The scale is meant to read like a player's Elo: around 600 for a single
+/// obvious move the engine finds at once, around 1500 for a two-move combination,
+/// and past 2500 for long lines with quiet moves only a deep search finds.
+pub const RATING_RANGE: RangeInclusive<u32> = 400..=3000;
+
+/// Rating of the easiest conceivable puzzle, before any factor adds to it
+const BASE_RATING: u32 = 600;
+
+/// Rating added per ply of search depth needed for the solution to stand out
+const PER_DEPTH: u32 = 40;
+
+/// Rating added per solver move past the first
+const PER_EXTRA_MOVE: u32 = 150;
+
+/// Rating added per solver move that is neither a capture, a check nor a promotion
+const PER_QUIET_MOVE: u32 = 250;
+
+/// Most rating a small eval swing can add, subtle gains being harder to spot
+const SUBTLETY_BONUS: f32 = 200.0;
+
+/// Eval swing in pawns from which a gain is obvious and adds no subtlety bonus
+const OBVIOUS_SWING: f32 = 10.0;
+
+/// What is known about a puzzle's solution when rating it
+#[derive(Debug, Clone, Copy)]
+pub struct RatingFactors {
+    /// Shallowest search depth at which the solution is clearly the best
+    /// move, 0 when unknown
+    pub find_depth: u8,
+    /// Eval swing of the solution in pawns, infinite for mates or when unknown
+    pub swing: f32,
+    /// Moves the solver has to find
+    pub solver_moves: usize,
+    /// Solver moves that are neither captures, checks nor promotions
+    pub quiet_moves: usize,
+}
+
+/// Estimates how hard a puzzle is, on the scale of [`RATING_RANGE`]
+///
+/// # Arguments
+/// * `factors` - Analysis data collected while generating the puzzle
+///
+/// # Returns
+/// The rating, clamped to [`RATING_RANGE`]
+pub fn rate_puzzle(factors: &RatingFactors) -> u32 {
+    let subtlety = 1.0 - factors.swing.clamp(0.0, OBVIOUS_SWING) / OBVIOUS_SWING;
+    let extra_moves = factors.solver_moves.saturating_sub(1) as u32;
+
+    let rating = BASE_RATING
+        + PER_DEPTH * u32::from(factors.find_depth)
+        + PER_EXTRA_MOVE * extra_moves
+        + PER_QUIET_MOVE * factors.quiet_moves as u32
+        + (SUBTLETY_BONUS * subtlety) as u32;
+
+    rating.clamp(*RATING_RANGE.start(), *RATING_RANGE.end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obvious_mate_in_one_is_easy() {
+        let rating = rate_puzzle(&RatingFactors {
+            find_depth: 1,
+            swing: f32::INFINITY,
+            solver_moves: 1,
+            quiet_moves: 0,
+        });
+        assert_eq!(rating, 640);
+    }
+
+    #[test]
+    fn deep_quiet_combination_is_hard() {
+        let rating = rate_puzzle(&RatingFactors {
+            find_depth: 16,
+            swing: 1.5,
+            solver_moves: 3,
+            quiet_moves: 2,
+        });
+        assert_eq!(rating, 2210);
+    }
+
+    #[test]
+    fn ratings_stay_in_range() {
+        let rating = rate_puzzle(&RatingFactors {
+            find_depth: u8::MAX,
+            swing: 0.0,
+            solver_moves: 20,
+            quiet_moves: 20,
+        });
+        assert_eq!(rating, *RATING_RANGE.end());
+    }
+}
@@ -1,82 +1,610 @@
+use std::cmp::Ordering;
+use std::env;
 use std::io::BufRead;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{
     fmt::{Debug, Display},
     io::{self, BufReader, BufWriter, Write as _},
     process::{Child, ChildStdin, ChildStdout, Stdio},
 };
 
-use tracing::info;
+use shakmaty::Color;
+use shakmaty::fen::Fen;
+use tracing::{info, warn};
 
-pub fn best_move_for_pos_moves(moves: &str, depth: u8, stockfish: &mut Stockfish) -> String {
-    stockfish.new_game().expect("can't start ucinewgame");
+use crate::common::config::Config;
 
-    let position_cmd = format!("position startpos moves {}", moves);
-    let depth_cmd = format!("go depth {}", depth);
+/// Environment variable pointing at the engine binary
+const STOCKFISH_PATH_VAR: &str = "STOCKFISH_PATH";
 
-    stockfish
-        .write(&position_cmd)
-        .expect("can't write to stockfish");
+/// Engine binary looked up on `PATH` when `STOCKFISH_PATH` isn't set
+const DEFAULT_BINARY: &str = "stockfish";
 
-    stockfish
-        .write(&depth_cmd)
-        .expect("can't write to stockfish");
+/// Environment variable with the number of search threads
+const THREADS_VAR: &str = "STOCKFISH_THREADS";
 
-    let output = stockfish.read_until("bestmove").unwrap();
+/// Environment variable with the hash size in MB
+const HASH_MB_VAR: &str = "STOCKFISH_HASH_MB";
 
-    let best_move = output.split_whitespace().nth(1).unwrap();
-    best_move.to_string()
+/// Longest wait for engine output, so a hung engine can't block forever
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Lines of output the engine may print in answer to `uci` before `uciok`
+const MAX_HANDSHAKE_LINES: usize = 200;
+
+pub fn best_move_for_pos_moves(moves: &str, depth: u8, stockfish: &mut Stockfish) -> Result<String, StockfishError> {
+    best_move(&format!("position startpos moves {moves}"), depth, stockfish)
+}
+
+pub fn best_move_for_pos(fen: &Fen, depth: u8, stockfish: &mut Stockfish) -> Result<String, StockfishError> {
+    best_move(&format!("position fen {fen}"), depth, stockfish)
+}
+
+/// Deepens the search one ply at a time while within the time budget, every
+/// search being given the time left as its `movetime`
+///
+/// # Arguments
+/// * `moves` - Moves in UCI notation played from the starting position
+/// * `max_depth` - Depth at which deepening stops even if time is left
+/// * `budget` - Wall-clock time the searches may take altogether
+/// * `stockfish` - Mutable reference to a Stockfish engine instance
+///
+/// # Returns
+/// The best move of the deepest search, the depth 1 one being run even when
+/// the budget is spent
+pub fn best_move_for_pos_moves_within(
+    moves: &str,
+    max_depth: u8,
+    budget: Duration,
+    stockfish: &mut Stockfish,
+) -> Result<String, StockfishError> {
+    best_move_deepening(
+        &format!("position startpos moves {moves}"),
+        max_depth,
+        budget,
+        stockfish,
+    )
+}
+
+/// Same as [`best_move_for_pos_moves_within`] for a position given as FEN
+pub fn best_move_for_pos_within(
+    fen: &Fen,
+    max_depth: u8,
+    budget: Duration,
+    stockfish: &mut Stockfish,
+) -> Result<String, StockfishError> {
+    best_move_deepening(&format!("position fen {fen}"), max_depth, budget, stockfish)
+}
+
+/// Searches for a fixed amount of time with `go movetime`
+///
+/// Unlike a fixed depth, whose cost varies wildly between positions, a fixed
+/// time keeps the wall-clock latency consistent, also under load.
+///
+/// # Arguments
+/// * `moves` - Moves in UCI notation played from the starting position
+/// * `millis` - Search time in milliseconds
+/// * `stockfish` - Mutable reference to a Stockfish engine instance
+pub fn best_move_for_pos_moves_timed(moves: &str, millis: u64, stockfish: &mut Stockfish) -> Result<String, StockfishError> {
+    search(
+        &format!("position startpos moves {moves}"),
+        &format!("go movetime {millis}"),
+        stockfish,
+    )
+}
+
+/// Same as [`best_move_for_pos_moves_timed`] for a position given as FEN
+pub fn best_move_for_pos_timed(fen: &Fen, millis: u64, stockfish: &mut Stockfish) -> Result<String, StockfishError> {
+    search(
+        &format!("position fen {fen}"),
+        &format!("go movetime {millis}"),
+        stockfish,
+    )
+}
+
+pub fn eval_pos_moves(moves: &str, stockfish: &mut Stockfish) -> Result<Evaluation, StockfishError> {
+    eval(&format!("position startpos moves {moves}"), stockfish)
+}
+
+pub fn eval_pos(fen: &Fen, stockfish: &mut Stockfish) -> Result<Evaluation, StockfishError> {
+    eval(&format!("position fen {fen}"), stockfish)
+}
+
+/// Evaluates a position by searching it rather than with the static `eval`,
+/// so the score accounts for tactics
+///
+/// # Arguments
+/// * `moves` - Moves in UCI notation played from the starting position
+/// * `depth` - Search depth
+/// * `stockfish` - Mutable reference to a Stockfish engine instance
+///
+/// # Returns
+/// The score of the deepest search from white's point of view
+pub fn eval_by_search(
+    moves: &str,
+    depth: u8,
+    stockfish: &mut Stockfish,
+) -> Result<Evaluation, StockfishError> {
+    let white_to_move = moves.split_whitespace().count().is_multiple_of(2);
+    let analysis = analyze(moves, depth, stockfish)?;
+    Ok(analysis.score.for_white(Color::from_white(white_to_move)))
+}
+
+/// Same as [`eval_by_search`] for a position given as FEN
+pub fn eval_by_search_for_pos(
+    fen: &Fen,
+    depth: u8,
+    stockfish: &mut Stockfish,
+) -> Result<Evaluation, StockfishError> {
+    let analysis = analyze_for_pos(fen, depth, stockfish)?;
+    Ok(analysis.score.for_white(fen.as_setup().turn))
+}
+
+fn best_move(position_cmd: &str, depth: u8, stockfish: &mut Stockfish) -> Result<String, StockfishError> {
+    search(position_cmd, &format!("go depth {depth}"), stockfish)
 }
 
-pub fn eval_pos_moves(moves: &str, stockfish: &mut Stockfish) -> Evaluation {
-    stockfish.new_game().expect("can't start ucinewgame");
+/// Runs a search with the given `go` command and reads back the best move
+fn search(position_cmd: &str, go_cmd: &str, stockfish: &mut Stockfish) -> Result<String, StockfishError> {
+    stockfish.prepare_search()?;
+    stockfish.write(position_cmd)?;
+    stockfish.write(go_cmd)?;
 
-    let position_cmd = format!("position startpos moves {moves}");
-    let eval_cmd = "eval";
+    let output = stockfish.read_until("bestmove")?;
+
+    match parse_bestmove(&output) {
+        // Reported for checkmate and stalemate
+        Some("(none)") => Err(StockfishError::GameOver),
+        Some(best_move) => Ok(best_move.to_string()),
+        None => Err(StockfishError::UnexpectedOutput(format!(
+            "no best move in {output:?}"
+        ))),
+    }
+}
 
-    stockfish
-        .write(&position_cmd)
-        .expect("could not write to stockfish");
+/// Extracts the move from a `bestmove` line, e.g. `bestmove e2e4 ponder e7e5`
+///
+/// # Returns
+/// The move, `(none)` when the game is over, or `None` if the line isn't a
+/// `bestmove` line
+fn parse_bestmove(line: &str) -> Option<&str> {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["bestmove", best_move, ..] => Some(best_move),
+        _ => None,
+    }
+}
 
-    stockfish
-        .write(eval_cmd)
-        .expect("could not write to stockfish");
+fn best_move_deepening(
+    position_cmd: &str,
+    max_depth: u8,
+    budget: Duration,
+    stockfish: &mut Stockfish,
+) -> Result<String, StockfishError> {
+    let started = Instant::now();
+    let mut best = None;
 
-    let output = stockfish.read_until("Final").unwrap();
+    for depth in 1..=max_depth.max(1) {
+        let remaining = budget.saturating_sub(started.elapsed());
+        if remaining.is_zero() && best.is_some() {
+            break;
+        }
+        // The engine stops on its own once the time left runs out, so a
+        // single deep search can't overrun the budget
+        let go_cmd = format!("go depth {depth} movetime {}", remaining.as_millis().max(1));
+        best = Some(search(position_cmd, &go_cmd, stockfish)?);
+    }
+
+    Ok(best.expect("at least one depth is searched"))
+}
+
+fn eval(position_cmd: &str, stockfish: &mut Stockfish) -> Result<Evaluation, StockfishError> {
+    stockfish.prepare_search()?;
+    stockfish.write(position_cmd)?;
+    stockfish.write("eval")?;
+
+    let output = stockfish.read_until("Final")?;
 
     if output.contains("in check") {
-        return Evaluation::Check;
+        return Ok(Evaluation::Check);
+    }
+
+    let eval = output
+        .split_whitespace()
+        .nth(2)
+        .and_then(|eval| eval.parse::<f32>().ok())
+        .ok_or_else(|| StockfishError::UnexpectedOutput(format!("no evaluation in {output:?}")))?;
+
+    Ok(Evaluation::Eval(eval))
+}
+
+/// Searches the position for its best few lines using MultiPV
+///
+/// # Arguments
+/// * `moves` - Moves in UCI notation played from the starting position
+/// * `depth` - Search depth
+/// * `count` - Number of lines to report
+/// * `stockfish` - Mutable reference to a Stockfish engine instance
+///
+/// # Returns
+/// First move and score of each line, best line first, none when the game is
+/// over. Scores are from the side to move's point of view
+pub fn top_lines(
+    moves: &str,
+    depth: u8,
+    count: usize,
+    stockfish: &mut Stockfish,
+) -> Result<Vec<(String, Evaluation)>, StockfishError> {
+    let lines = multi_pv(
+        &format!("position startpos moves {moves}"),
+        depth,
+        count,
+        stockfish,
+    )?;
+    Ok(lines.into_iter().map(first_move).collect())
+}
+
+/// Same as [`top_lines`] for a position given as FEN
+pub fn top_lines_for_pos(
+    fen: &Fen,
+    depth: u8,
+    count: usize,
+    stockfish: &mut Stockfish,
+) -> Result<Vec<(String, Evaluation)>, StockfishError> {
+    let lines = multi_pv(&format!("position fen {fen}"), depth, count, stockfish)?;
+    Ok(lines.into_iter().map(first_move).collect())
+}
+
+/// Same as [`top_lines_for_pos`], keeping the whole principal variation of
+/// every line
+pub fn top_variations_for_pos(
+    fen: &Fen,
+    depth: u8,
+    count: usize,
+    stockfish: &mut Stockfish,
+) -> Result<Vec<Analysis>, StockfishError> {
+    multi_pv(&format!("position fen {fen}"), depth, count, stockfish)
+}
+
+fn first_move(line: Analysis) -> (String, Evaluation) {
+    let Analysis { mut pv, score, .. } = line;
+    (pv.swap_remove(0), score)
+}
+
+fn multi_pv(
+    position_cmd: &str,
+    depth: u8,
+    count: usize,
+    stockfish: &mut Stockfish,
+) -> Result<Vec<Analysis>, StockfishError> {
+    stockfish.set_option("MultiPV", &count.to_string())?;
+    let searched = multi_pv_search(position_cmd, depth, stockfish);
+    // Later searches expect a single line, whether this one went through or not
+    let reset = stockfish.set_option("MultiPV", "1");
+    let output = searched?;
+    reset?;
+
+    // Lines are reported again at every depth, the last report of each wins
+    let mut lines: Vec<Option<Analysis>> = vec![None; count];
+    for (idx, line) in output.iter().filter_map(|line| parse_pv_line(line)) {
+        if let Some(slot) = idx.checked_sub(1).and_then(|idx| lines.get_mut(idx)) {
+            *slot = Some(line);
+        }
+    }
+
+    Ok(lines.into_iter().flatten().collect())
+}
+
+fn multi_pv_search(
+    position_cmd: &str,
+    depth: u8,
+    stockfish: &mut Stockfish,
+) -> Result<Vec<String>, StockfishError> {
+    stockfish.prepare_search()?;
+    stockfish.write(position_cmd)?;
+    stockfish.write(&format!("go depth {depth}"))?;
+
+    Ok(stockfish.read_lines_until("bestmove")?)
+}
+
+/// Extracts the MultiPV index, score and principal variation from an `info`
+/// line, skipping lines whose score is only a bound or that have no move
+fn parse_pv_line(line: &str) -> Option<(usize, Analysis)> {
+    let info = parse_info(line).filter(|info| !info.bound && !info.pv.is_empty())?;
+    let analysis = Analysis {
+        depth: info.depth.unwrap_or_default(),
+        score: info.score?,
+        pv: info.pv,
+    };
+    Some((info.multipv.unwrap_or(1), analysis))
+}
+
+/// Search progress reported by the engine in an `info` line
+#[derive(Debug, Clone, Default)]
+pub struct InfoLine {
+    pub depth: Option<u32>,
+    pub seldepth: Option<u32>,
+    /// Which of the MultiPV lines this is, 1 being the best
+    pub multipv: Option<usize>,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    /// Score from the side to move's point of view
+    pub score: Option<Evaluation>,
+    /// The score is only a lower or upper bound, reported when the search
+    /// fails high or low, and is no evaluation of the position
+    pub bound: bool,
+    /// Principal variation in UCI notation, the best move first
+    pub pv: Vec<String>,
+}
+
+/// Tokenizes a UCI `info` line into its fields
+///
+/// # Arguments
+/// * `line` - Line of engine output, e.g. `info depth 12 score cp 35 pv e2e4 e7e5`
+///
+/// # Returns
+/// The parsed fields, or `None` if the line isn't an `info` line
+pub fn parse_info(line: &str) -> Option<InfoLine> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let (&"info", mut rest) = tokens.split_first()? else {
+        return None;
+    };
+
+    let mut info = InfoLine::default();
+    while let Some((key, tail)) = rest.split_first() {
+        rest = tail;
+        match *key {
+            "depth" => info.depth = rest.first().and_then(|v| v.parse().ok()),
+            "seldepth" => info.seldepth = rest.first().and_then(|v| v.parse().ok()),
+            "multipv" => info.multipv = rest.first().and_then(|v| v.parse().ok()),
+            "nodes" => info.nodes = rest.first().and_then(|v| v.parse().ok()),
+            "nps" => info.nps = rest.first().and_then(|v| v.parse().ok()),
+            "score" => {
+                info.score = parse_score(rest);
+                // The score kind and value, the bound flags are skipped as keys
+                rest = rest.get(2..).unwrap_or_default();
+                continue;
+            }
+            "pv" => {
+                info.pv = rest.iter().map(|mv| mv.to_string()).collect();
+                break;
+            }
+            // Free text or move lists running to the end of the line
+            "string" | "refutation" | "currline" => break,
+            "lowerbound" | "upperbound" => {
+                info.bound = true;
+                continue;
+            }
+            _ => {}
+        }
+        // Every other key is followed by a single value
+        rest = rest.get(1..).unwrap_or_default();
+    }
+
+    Some(info)
+}
+
+/// Outcome of a single search
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    /// Deepest depth the search completed
+    pub depth: u32,
+    /// Score at that depth from the side to move's point of view
+    pub score: Evaluation,
+    /// Principal variation in UCI notation, the best move first
+    pub pv: Vec<String>,
+}
+
+/// Searches a position and keeps the score and principal variation of the
+/// final depth, so a whole line comes out of one search
+///
+/// # Arguments
+/// * `moves` - Moves in UCI notation played from the starting position
+/// * `depth` - Search depth
+/// * `stockfish` - Mutable reference to a Stockfish engine instance
+pub fn analyze(moves: &str, depth: u8, stockfish: &mut Stockfish) -> Result<Analysis, StockfishError> {
+    run_analysis(&format!("position startpos moves {moves}"), depth, stockfish)
+}
+
+/// Same as [`analyze`] for a position given as FEN
+pub fn analyze_for_pos(fen: &Fen, depth: u8, stockfish: &mut Stockfish) -> Result<Analysis, StockfishError> {
+    run_analysis(&format!("position fen {fen}"), depth, stockfish)
+}
+
+fn run_analysis(
+    position_cmd: &str,
+    depth: u8,
+    stockfish: &mut Stockfish,
+) -> Result<Analysis, StockfishError> {
+    stockfish.prepare_search()?;
+    stockfish.write(position_cmd)?;
+    stockfish.write(&format!("go depth {depth}"))?;
+
+    let output = stockfish.read_lines_until("bestmove")?;
+    if output
+        .last()
+        .is_some_and(|line| parse_bestmove(line) == Some("(none)"))
+    {
+        return Err(StockfishError::GameOver);
+    }
+
+    output
+        .iter()
+        .rev()
+        .filter_map(|line| parse_info(line))
+        .filter(|info| info.multipv.unwrap_or(1) == 1 && !info.pv.is_empty() && !info.bound)
+        .filter_map(|info| {
+            Some(Analysis {
+                depth: info.depth?,
+                score: info.score?,
+                pv: info.pv,
+            })
+        })
+        .next()
+        .ok_or_else(|| StockfishError::UnexpectedOutput(format!("no scored line in {output:?}")))
+}
+
+/// Parses the tokens following `score` in an `info` line, e.g. `cp 35` or `mate -2`
+fn parse_score(tokens: &[&str]) -> Option<Evaluation> {
+    match tokens {
+        ["cp", value, ..] => Some(Evaluation::Eval(value.parse::<f32>().ok()? / 100.0)),
+        ["mate", value, ..] => Some(Evaluation::Mate(value.parse().ok()?)),
+        _ => None,
     }
+}
 
-    let eval_str = output.split_whitespace().nth(2).unwrap();
-    let eval = eval_str
-        .parse::<f32>()
-        .unwrap_or_else(|err| panic!("could not parse {eval_str}: {err}"));
+/// Splits a total hash budget evenly across engines
+///
+/// # Arguments
+/// * `total_mb` - Hash budget in MB for all engines together
+/// * `engines` - Number of engines sharing the budget
+///
+/// # Returns
+/// Hash size in MB for each engine, never below 1
+pub fn hash_per_engine(total_mb: usize, engines: usize) -> usize {
+    (total_mb / engines.max(1)).max(1)
+}
 
-    Evaluation::Eval(eval)
+/// Reasons a request to the engine can fail
+#[derive(Debug)]
+pub enum StockfishError {
+    /// Talking to the engine process failed or timed out
+    Io(io::Error),
+    /// The engine answered with output that couldn't be made sense of
+    UnexpectedOutput(String),
+    /// The position is checkmate or stalemate, so there's no move to search
+    GameOver,
 }
 
+impl Display for StockfishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StockfishError::Io(e) => write!(f, "can't talk to stockfish: {e}"),
+            StockfishError::UnexpectedOutput(e) => write!(f, "unexpected stockfish output: {e}"),
+            StockfishError::GameOver => write!(f, "the game is over, there's no move to search"),
+        }
+    }
+}
+
+impl From<io::Error> for StockfishError {
+    fn from(err: io::Error) -> Self {
+        StockfishError::Io(err)
+    }
+}
+
+/// Pawn value a forced mate is worth, less one pawn per move it takes
+const MATE_PAWNS: f32 = 1000.0;
+
 /// Represents the evaluation of a chess position
+///
+/// Evaluations are from white's point of view, positive favoring white, with
+/// the exception of raw search scores: those in [`InfoLine`], [`Analysis`] and
+/// [`top_lines`] are from the side to move's point of view until turned into
+/// white's with [`Evaluation::for_white`]. Only evaluations of the same point
+/// of view may be compared.
+#[derive(Clone)]
 pub enum Evaluation {
     /// Position where the side to move is in check
     Check,
-    /// Numerical evaluation (positive favors white, negative favors black)
+    /// Numerical evaluation in pawns
     Eval(f32),
+    /// Forced mate in the given number of moves, positive when the side the
+    /// evaluation is for mates and negative or zero when it gets mated
+    Mate(i32),
 }
 
-impl Debug for Evaluation {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Evaluation {
+    /// Turns a score from the side to move's point of view into one from white's
+    ///
+    /// # Arguments
+    /// * `side_to_move` - Side the score was reported for
+    pub fn for_white(self, side_to_move: Color) -> Evaluation {
         match self {
-            Evaluation::Check => write!(f, "in check"),
-            Evaluation::Eval(eval) => write!(f, "{eval}"),
+            Evaluation::Check => Evaluation::Check,
+            Evaluation::Eval(eval) => Evaluation::Eval(side_to_move.fold_wb(eval, -eval)),
+            Evaluation::Mate(moves) => Evaluation::Mate(side_to_move.fold_wb(moves, -moves)),
+        }
+    }
+
+    /// Evaluation in pawns, mates counting as [`MATE_PAWNS`] less their length
+    /// so that quicker mates rank higher
+    ///
+    /// # Returns
+    /// The value, or `None` for a position in check which has no evaluation
+    pub fn pawns(&self) -> Option<f32> {
+        match self {
+            Evaluation::Check => None,
+            Evaluation::Eval(eval) => Some(*eval),
+            Evaluation::Mate(moves) if *moves > 0 => Some(MATE_PAWNS - *moves as f32),
+            Evaluation::Mate(moves) => Some(-MATE_PAWNS - *moves as f32),
+        }
+    }
+
+    /// Turns a score from the side to move's point of view into centipawns
+    /// from white's, mates counting as in [`Evaluation::pawns`]
+    ///
+    /// # Arguments
+    /// * `side_to_move` - Side the score was reported for
+    ///
+    /// # Returns
+    /// The value in centipawns, zero for a position in check which has no evaluation
+    pub fn to_centipawns_white(&self, side_to_move: Color) -> f32 {
+        self.clone()
+            .for_white(side_to_move)
+            .pawns()
+            .map_or(0.0, |pawns| pawns * 100.0)
+    }
+
+    /// Tier and value the ordering compares: getting mated below any
+    /// numerical evaluation, mating above it, each ordered by distance
+    fn rank(&self) -> (i8, f32) {
+        match self {
+            Evaluation::Check => (-2, 0.0),
+            Evaluation::Mate(moves) if *moves <= 0 => (-1, -*moves as f32),
+            Evaluation::Eval(eval) => (0, *eval),
+            Evaluation::Mate(moves) => (1, -*moves as f32),
         }
     }
 }
 
+impl PartialEq for Evaluation {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Evaluation {}
+
+impl PartialOrd for Evaluation {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Evaluation {
+    /// Orders evaluations from worst to best for the side they are for, a
+    /// position in check ranking below all as it has no evaluation
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (tier, value) = self.rank();
+        let (other_tier, other_value) = other.rank();
+        tier.cmp(&other_tier).then_with(|| value.total_cmp(&other_value))
+    }
+}
+
+impl Debug for Evaluation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
 impl Display for Evaluation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Evaluation::Check => write!(f, "in check"),
             Evaluation::Eval(eval) => write!(f, "{eval}"),
+            Evaluation::Mate(moves) => write!(f, "#{moves}"),
         }
     }
 }
@@ -84,27 +612,253 @@ impl Display for Evaluation {
 pub struct Stockfish {
     process: Child,
     writer: BufWriter<ChildStdin>,
-    pub reader: BufReader<ChildStdout>,
+    /// Lines of engine output, read on a separate thread so reads can time out
+    lines: Receiver<String>,
+    /// Longest wait for the next line of output, `None` waiting indefinitely
+    read_timeout: Option<Duration>,
+    /// Start every search and evaluation from a fresh game, clearing the hash
+    reset_each_call: bool,
+    /// Number of requests this engine has served since it was spawned
+    served: u64,
+    /// Number of reads that timed out waiting for the engine since it was spawned
+    timeouts: u64,
+    /// The engine didn't stop after a read timed out, so its output can't be
+    /// trusted anymore and it has to be respawned
+    broken: bool,
+    /// Engine name reported in the UCI handshake, e.g. `Stockfish 16.1`
+    pub name: Option<String>,
+    /// Engine authors reported in the UCI handshake
+    pub author: Option<String>,
 }
 
 impl Stockfish {
+    /// Spawns the engine found at `STOCKFISH_PATH`, or `stockfish` on `PATH`,
+    /// applying `STOCKFISH_THREADS` and `STOCKFISH_HASH_MB` when they're set
     pub fn try_init() -> Result<Self, io::Error> {
-        let mut process = std::process::Command::new("stockfish")
+        let mut stockfish = match env::var(STOCKFISH_PATH_VAR) {
+            Ok(path) => Self::with_path(path)?,
+            Err(_) => Self::with_path(DEFAULT_BINARY)?,
+        };
+
+        if let Some(threads) = env_option(THREADS_VAR)? {
+            stockfish.set_option("Threads", &threads.to_string())?;
+        }
+        if let Some(hash_mb) = env_option(HASH_MB_VAR)? {
+            stockfish.set_hash(hash_mb)?;
+        }
+
+        Ok(stockfish)
+    }
+
+    /// Spawns the engine binary at the given path
+    ///
+    /// # Arguments
+    /// * `path` - Path to the binary, or its name if it's on `PATH`
+    pub fn with_path(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let path = path.as_ref();
+        let mut process = std::process::Command::new(path)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .spawn()?;
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no stockfish binary at {}", path.display()),
+                ),
+                _ => io::Error::new(
+                    e.kind(),
+                    format!("can't spawn stockfish from {}: {e}", path.display()),
+                ),
+            })?;
 
         let stdin = process.stdin.take().expect("stockfish stdin error");
         let stdout = process.stdout.take().expect("stockfish stdout error");
 
         let writer = BufWriter::new(stdin);
-        let reader = BufReader::new(stdout);
+        let lines = spawn_reader(BufReader::new(stdout))?;
 
-        Ok(Stockfish {
+        let mut stockfish = Stockfish {
             process,
             writer,
-            reader,
-        })
+            lines,
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+            reset_each_call: false,
+            served: 0,
+            timeouts: 0,
+            broken: false,
+            name: None,
+            author: None,
+        };
+        stockfish.handshake()?;
+
+        Ok(stockfish)
+    }
+
+    /// Switches the engine to UCI mode and waits until it's ready, recording
+    /// the name and author it reports
+    fn handshake(&mut self) -> io::Result<()> {
+        self.write("uci")?;
+
+        let mut got_uciok = false;
+        for _ in 0..MAX_HANDSHAKE_LINES {
+            let Some(buffer) = self.next_line()? else {
+                break;
+            };
+
+            let line = buffer.trim();
+            if let Some(name) = line.strip_prefix("id name ") {
+                self.name = Some(name.to_string());
+            } else if let Some(author) = line.strip_prefix("id author ") {
+                self.author = Some(author.to_string());
+            } else if line == "uciok" {
+                got_uciok = true;
+                break;
+            }
+        }
+
+        if !got_uciok {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "engine didn't answer uci with uciok",
+            ));
+        }
+
+        self.is_ready()
+    }
+
+    /// Spawns an engine and applies the engine options from the config
+    pub fn from_config(conf: &Config) -> Result<Self, io::Error> {
+        let mut stockfish = Self::try_init()?;
+        info!(
+            "engine {} by {}",
+            stockfish.name.as_deref().unwrap_or("unknown"),
+            stockfish.author.as_deref().unwrap_or("unknown")
+        );
+
+        if let Some(total_hash_mb) = conf.total_hash_mb {
+            let hash_mb = hash_per_engine(total_hash_mb, conf.engine_pool_size);
+            stockfish.set_hash(hash_mb)?;
+            info!("engine hash set to {hash_mb}MB");
+        }
+
+        match &conf.syzygy_path {
+            Some(path) => {
+                stockfish.set_option("SyzygyPath", path)?;
+                info!("engine probes syzygy tablebases from {path}");
+            }
+            None => info!("no syzygy tablebases configured"),
+        }
+
+        Ok(stockfish)
+    }
+
+    /// Checks that the engine still answers `isready`
+    pub fn is_ready(&mut self) -> io::Result<()> {
+        self.write("isready")?;
+        let output = self.read_until("readyok")?;
+
+        if output.contains("readyok") {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "engine closed its output before readyok",
+            ))
+        }
+    }
+
+    /// Sets how long to wait for engine output before giving up on a command
+    ///
+    /// # Arguments
+    /// * `timeout` - Longest wait between two lines, `None` to wait indefinitely
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// Makes every search and evaluation start from a fresh game, for callers
+    /// that need calls isolated from each other at the cost of throughput
+    pub fn set_reset_each_call(&mut self, reset: bool) {
+        self.reset_each_call = reset;
+    }
+
+    /// Tells the engine a new game starts, clearing what it learned so far
+    ///
+    /// Call once per game; positions of the same game then share the hash.
+    pub fn begin_game(&mut self) -> io::Result<()> {
+        self.new_game()
+    }
+
+    pub fn record_request(&mut self) {
+        self.served += 1;
+    }
+
+    pub fn served(&self) -> u64 {
+        self.served
+    }
+
+    pub fn timeouts(&self) -> u64 {
+        self.timeouts
+    }
+
+    /// Whether the engine stopped answering and has to be respawned, every
+    /// command failing until then
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Sets the size of the engine's hash table
+    ///
+    /// # Arguments
+    /// * `mb` - Hash size in MB
+    pub fn set_hash(&mut self, mb: usize) -> io::Result<()> {
+        self.set_option("Hash", &mb.to_string())
+    }
+
+    /// Sets the number of search threads and the hash size at once
+    ///
+    /// # Arguments
+    /// * `threads` - Number of search threads
+    /// * `hash_mb` - Hash size in MB
+    pub fn configure(&mut self, threads: usize, hash_mb: usize) -> io::Result<()> {
+        self.set_option("Threads", &threads.to_string())?;
+        self.set_hash(hash_mb)
+    }
+
+    /// Sets a UCI option and waits for the engine to apply it
+    ///
+    /// # Arguments
+    /// * `name` - Option name as listed by the engine, e.g. `Threads`
+    /// * `value` - Value to set the option to
+    pub fn set_option(&mut self, name: &str, value: &str) -> io::Result<()> {
+        self.write(&format!("setoption name {name} value {value}"))?;
+        self.write("isready")?;
+        self.read_until("readyok")?;
+        Ok(())
+    }
+
+    /// Counts the leaf nodes of the move tree below a position with `go perft`
+    ///
+    /// Useful for checking that a position reconstructed by the crate matches
+    /// the engine's understanding of it.
+    ///
+    /// # Arguments
+    /// * `fen` - Position to count from
+    /// * `depth` - Number of plies to expand
+    pub fn perft(&mut self, fen: &Fen, depth: u8) -> io::Result<u64> {
+        self.write(&format!("position fen {fen}"))?;
+        self.write(&format!("go perft {depth}"))?;
+
+        let output = self.read_until("Nodes searched")?;
+        output
+            .split(':')
+            .nth(1)
+            .and_then(|nodes| nodes.trim().parse().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected perft output: {}", output.trim()),
+                )
+            })
     }
 
     /// Sends a command to the Stockfish engine
@@ -115,6 +869,12 @@ impl Stockfish {
     /// # Returns
     /// An io::Result indicating success or failure
     fn write(&mut self, cmd: &str) -> io::Result<()> {
+        if self.broken {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "engine stopped answering and has to be respawned",
+            ));
+        }
         writeln!(self.writer, "{}", cmd)?;
         self.writer.flush()?;
         Ok(())
@@ -126,46 +886,459 @@ impl Stockfish {
     /// An io::Result indicating success or failure
     fn new_game(&mut self) -> io::Result<()> {
         // Send new game command
-        writeln!(self.writer, "ucinewgame")?;
+        self.write("ucinewgame")?;
         // Wait for engine to be ready
-        writeln!(self.writer, "isready")?;
-        self.writer.flush()?;
+        self.write("isready")?;
         self.read_until("readyok")?;
         Ok(())
     }
 
-    /// Reads output from Stockfish until a specific marker is found
-    fn read_until(&mut self, marker: &str) -> Result<String, io::Error> {
-        let mut buffer = String::new();
+    /// Waits for the next line of engine output
+    ///
+    /// On a timeout the engine is stopped and its pending output dropped, see
+    /// [`Stockfish::resync`], so the next command doesn't read a late answer.
+    ///
+    /// # Returns
+    /// The line, `None` once the engine closed its output, or a `TimedOut`
+    /// error if no line arrived within the read timeout
+    fn next_line(&mut self) -> io::Result<Option<String>> {
+        let received = match self.read_timeout {
+            Some(timeout) => self.lines.recv_timeout(timeout),
+            None => self
+                .lines
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
 
-        loop {
-            buffer.clear();
-            let bytes_read = self.reader.read_line(&mut buffer)?;
+        match received {
+            Ok(line) => Ok(Some(line)),
+            Err(RecvTimeoutError::Disconnected) => Ok(None),
+            Err(RecvTimeoutError::Timeout) => {
+                self.timeouts += 1;
+                let waited = self.read_timeout.unwrap_or_default();
+                self.resync(waited);
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("engine sent no output for {waited:?}"),
+                ))
+            }
+        }
+    }
 
-            if bytes_read == 0 {
-                break;
+    /// Stops whatever the engine is still busy with and drops the output it
+    /// has pending, up to its answer to `isready`
+    ///
+    /// An engine that doesn't answer within `wait` is marked broken, see
+    /// [`Stockfish::is_broken`].
+    fn resync(&mut self, wait: Duration) {
+        if self.write("stop").and_then(|_| self.write("isready")).is_ok() {
+            let deadline = Instant::now() + wait;
+            while let Ok(line) = self
+                .lines
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                if starts_with_marker(line.trim(), "readyok") {
+                    return;
+                }
+            }
+        }
+
+        warn!("engine didn't stop within {wait:?} of a timed out read, it has to be respawned");
+        self.broken = true;
+    }
+
+    /// Starts a fresh game before a search when every call is to be isolated
+    fn prepare_search(&mut self) -> io::Result<()> {
+        if self.reset_each_call {
+            self.new_game()?;
+        }
+        Ok(())
+    }
+
+    /// Reads output from Stockfish until a line starting with `marker` is found
+    ///
+    /// # Returns
+    /// The line with the marker, trimmed, or an empty string if the engine
+    /// closed its output first
+    fn read_until(&mut self, marker: &str) -> Result<String, io::Error> {
+        while let Some(line) = self.next_line()? {
+            let trimmed = line.trim();
+
+            // Exit when marker is found
+            if starts_with_marker(trimmed, marker) {
+                return Ok(trimmed.to_string());
             }
+        }
+
+        Ok(String::new())
+    }
 
-            let trimmed = buffer.trim();
-            // Skip empty lines
+    /// Reads output from Stockfish until a line starting with `marker` is
+    /// found, keeping every non-empty line including the one with the marker
+    fn read_lines_until(&mut self, marker: &str) -> Result<Vec<String>, io::Error> {
+        let mut lines = Vec::new();
+
+        while let Some(line) = self.next_line()? {
+            let trimmed = line.trim();
             if trimmed.is_empty() {
                 continue;
             }
 
-            // Exit when marker is found
-            if trimmed == marker || trimmed.contains(marker) {
+            lines.push(trimmed.to_string());
+            if starts_with_marker(trimmed, marker) {
                 break;
             }
         }
 
-        Ok(buffer)
+        Ok(lines)
+    }
+}
+
+/// Tells whether a line of engine output starts with `marker` as a whole
+/// word, so a marker mentioned in the middle of e.g. an `info string` line
+/// doesn't match
+fn starts_with_marker(line: &str, marker: &str) -> bool {
+    line.strip_prefix(marker)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric()))
+}
+
+/// Reads a numeric engine option from the environment
+fn env_option(key: &str) -> io::Result<Option<usize>> {
+    match env::var(key) {
+        Ok(value) => value.parse().map(Some).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{key} must be a number, got {value}: {e}"),
+            )
+        }),
+        Err(_) => Ok(None),
     }
 }
 
+/// Forwards the engine's output line by line over a channel, which
+/// disconnects once the engine closes its output
+fn spawn_reader(mut reader: BufReader<ChildStdout>) -> io::Result<Receiver<String>> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::Builder::new()
+        .name("stockfish-reader".to_string())
+        .spawn(move || {
+            loop {
+                let mut line = String::new();
+                match reader.read_line(&mut line) {
+                    Ok(0) => break,
+                    Ok(_) => {
+                        if sender.send(line).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("can't read engine output: {e}");
+                        break;
+                    }
+                }
+            }
+        })?;
+
+    Ok(receiver)
+}
+
 impl Drop for Stockfish {
     fn drop(&mut self) {
+        // A broken engine may not read its input anymore, so it won't quit by itself
+        if self.broken {
+            let _ = self.process.kill();
+        }
         let _ = self.write("quit");
         let _ = self.process.wait();
         info!("stockfish terminated successfully");
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use shakmaty::{CastlingMode, Chess};
+
+    use super::*;
+
+    /// Answers to the UCI handshake and to `isready`, for fake engines to start with
+    pub(crate) const HANDSHAKE: &str = r#"
+    uci) echo "id name FakeFish"; echo "uciok";;
+    isready) echo "readyok";;
+    quit) exit 0;;"#;
+
+    /// Writes a shell script to a fresh temporary file and makes it executable
+    ///
+    /// # Arguments
+    /// * `cases` - Arms of a `case "$line" in` over every line of input, tried
+    ///   before those of [`HANDSHAKE`], `$pos` holding the last `position` command
+    pub(crate) fn fake_engine_script(cases: &str) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "fakefish-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let script = format!(
+            "#!/bin/sh\npos=\"\"\nwhile read line; do\n  case \"$line\" in\n    \
+             position*) pos=\"$line\";;{cases}{HANDSHAKE}\n  esac\ndone\n"
+        );
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// Spawns a fake engine made of the given `case` arms, see [`fake_engine_script`]
+    pub(crate) fn fake_engine(cases: &str) -> Stockfish {
+        let path = fake_engine_script(cases);
+        // Another test forking while the script was being written keeps it
+        // busy for a moment
+        let mut attempts = 0;
+        loop {
+            match Stockfish::with_path(&path) {
+                Ok(stockfish) => return stockfish,
+                Err(e) if e.raw_os_error() == Some(26) && attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => panic!("can't spawn fake engine: {e}"),
+            }
+        }
+    }
+
+    fn start_fen() -> Fen {
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn deepening_searches_are_bounded_by_the_time_left() {
+        // Searches without a movetime would answer a2a3
+        let mut stockfish = fake_engine(
+            r#"
+    "go depth 1 movetime "*) echo "bestmove e2e4";;
+    "go depth 2 movetime "*) sleep 0.3; echo "bestmove d2d4";;
+    "go depth 3 movetime "*) echo "bestmove c2c4";;
+    go*) echo "bestmove a2a3";;"#,
+        );
+
+        let best =
+            best_move_for_pos_within(&start_fen(), 3, Duration::from_secs(5), &mut stockfish);
+        assert_eq!(best.unwrap(), "c2c4");
+
+        // The depth 2 search spends the budget, so no deeper one starts
+        let best =
+            best_move_for_pos_within(&start_fen(), 5, Duration::from_millis(100), &mut stockfish);
+        assert_eq!(best.unwrap(), "d2d4");
+
+        let best = best_move_for_pos_within(&start_fen(), 5, Duration::ZERO, &mut stockfish);
+        assert_eq!(best.unwrap(), "e2e4");
+    }
+
+    #[test]
+    fn silent_engine_times_out_and_is_marked_broken() {
+        let mut stockfish = fake_engine(
+            r#"
+    go*) sleep 30;;"#,
+        );
+        stockfish.set_read_timeout(Some(Duration::from_millis(200)));
+
+        let started = Instant::now();
+        let err = best_move_for_pos(&start_fen(), 5, &mut stockfish).unwrap_err();
+        assert!(matches!(err, StockfishError::Io(ref e) if e.kind() == io::ErrorKind::TimedOut));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(stockfish.timeouts(), 1);
+        assert!(stockfish.is_broken());
+
+        // A broken engine fails fast rather than reading stale output
+        assert!(best_move_for_pos(&start_fen(), 5, &mut stockfish).is_err());
+    }
+
+    #[test]
+    fn late_answer_isnt_taken_for_the_next_search() {
+        let mut stockfish = fake_engine(
+            r#"
+    stop) ;;
+    go*)
+      case "$pos" in
+        *slow*) sleep 1; echo "bestmove a2a3";;
+        *) echo "bestmove e2e4";;
+      esac;;"#,
+        );
+        stockfish.set_read_timeout(Some(Duration::from_millis(700)));
+
+        let slow = search("position slow", "go depth 5", &mut stockfish);
+        assert!(slow.is_err());
+        assert!(!stockfish.is_broken());
+
+        let best = best_move_for_pos(&start_fen(), 5, &mut stockfish).unwrap();
+        assert_eq!(best, "e2e4");
+    }
+
+    #[test]
+    fn multi_pv_is_reset_after_a_failed_search() {
+        let mut stockfish = fake_engine(
+            r#"
+    stop) ;;
+    setoption*) option="$line";;
+    go*)
+      case "$pos" in
+        *slow*) sleep 1; echo "bestmove a2a3";;
+        *)
+          case "$option" in
+            *"MultiPV value 1") echo "bestmove e2e4";;
+            *) echo "bestmove h2h3";;
+          esac;;
+      esac;;"#,
+        );
+        stockfish.set_read_timeout(Some(Duration::from_millis(700)));
+
+        let slow = multi_pv("position slow", 5, 3, &mut stockfish);
+        assert!(slow.is_err());
+        assert!(!stockfish.is_broken());
+
+        let best = best_move_for_pos(&start_fen(), 5, &mut stockfish).unwrap();
+        assert_eq!(best, "e2e4");
+    }
+
+    #[test]
+    fn bestmove_is_taken_from_the_bestmove_line_only() {
+        let mut stockfish = fake_engine(
+            r#"
+    go*)
+      echo "info string NNUE evaluation using nn.nnue, bestmove comes later"
+      echo "info depth 0 score cp 0"
+      echo "info depth 1 seldepth 1 multipv 1 score cp 50 lowerbound nodes 20 pv d2d4"
+      echo "info depth 1 seldepth 1 multipv 1 score cp 20 nodes 40 pv e2e4 e7e5"
+      echo "bestmove e2e4 ponder e7e5";;"#,
+        );
+
+        assert_eq!(best_move_for_pos(&start_fen(), 1, &mut stockfish).unwrap(), "e2e4");
+
+        let analysis = analyze_for_pos(&start_fen(), 1, &mut stockfish).unwrap();
+        assert_eq!(analysis.score, Evaluation::Eval(0.2));
+        assert_eq!(analysis.pv, ["e2e4", "e7e5"]);
+
+        let lines = top_lines_for_pos(&start_fen(), 1, 1, &mut stockfish).unwrap();
+        assert_eq!(lines, [("e2e4".to_string(), Evaluation::Eval(0.2))]);
+    }
+
+    #[test]
+    fn game_over_is_reported() {
+        let mut stockfish = fake_engine(
+            r#"
+    go*) echo "info depth 0 score mate 0"; echo "bestmove (none)";;"#,
+        );
+
+        let err = best_move_for_pos(&start_fen(), 5, &mut stockfish).unwrap_err();
+        assert!(matches!(err, StockfishError::GameOver));
+    }
+
+    #[test]
+    fn static_evaluation_is_read_for_white() {
+        let mut stockfish = fake_engine(
+            r#"
+    eval)
+      echo "info string the Final word"
+      echo "Final evaluation       -0.45 (white side)";;"#,
+        );
+
+        assert_eq!(eval_pos(&start_fen(), &mut stockfish).unwrap(), Evaluation::Eval(-0.45));
+    }
+
+    #[test]
+    fn marker_must_start_the_line() {
+        assert!(starts_with_marker("bestmove e2e4 ponder e7e5", "bestmove"));
+        assert!(starts_with_marker("Nodes searched: 20", "Nodes searched"));
+        assert!(!starts_with_marker("info string bestmove soon", "bestmove"));
+        assert!(!starts_with_marker("bestmoves e2e4", "bestmove"));
+    }
+
+    #[test]
+    fn parses_info_lines() {
+        let info = parse_info(concat!(
+            "info depth 12 seldepth 18 multipv 2 score mate -3 upperbound ",
+            "nodes 1000 nps 500 pv e2e4 e7e5"
+        ))
+        .unwrap();
+        assert_eq!(info.depth, Some(12));
+        assert_eq!(info.seldepth, Some(18));
+        assert_eq!(info.multipv, Some(2));
+        assert_eq!(info.score, Some(Evaluation::Mate(-3)));
+        assert!(info.bound);
+        assert_eq!(info.nodes, Some(1000));
+        assert_eq!(info.pv, ["e2e4", "e7e5"]);
+
+        assert!(parse_info("bestmove e2e4").is_none());
+        assert!(!parse_info("info depth 3 score cp 12 pv d2d4").unwrap().bound);
+    }
+
+    #[test]
+    fn evaluations_order_from_worst_to_best() {
+        let mut evals = vec![
+            Evaluation::Mate(2),
+            Evaluation::Eval(1.5),
+            Evaluation::Mate(-1),
+            Evaluation::Check,
+            Evaluation::Mate(5),
+            Evaluation::Eval(-3.0),
+        ];
+        evals.sort();
+        assert_eq!(
+            evals,
+            [
+                Evaluation::Check,
+                Evaluation::Mate(-1),
+                Evaluation::Eval(-3.0),
+                Evaluation::Eval(1.5),
+                Evaluation::Mate(5),
+                Evaluation::Mate(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_hash_across_engines() {
+        assert_eq!(hash_per_engine(256, 4), 64);
+        assert_eq!(hash_per_engine(2, 4), 1);
+        assert_eq!(hash_per_engine(16, 0), 16);
+    }
+
+    #[test]
+    fn perft_reads_the_node_count() {
+        let mut stockfish = fake_engine(
+            r#"
+    "go perft 1") echo "a2a3: 1"; echo "b2b3: 1"; echo; echo "Nodes searched: 20";;"#,
+        );
+
+        assert_eq!(stockfish.perft(&start_fen(), 1).unwrap(), 20);
+    }
+
+    #[test]
+    fn perft_matches_move_generation() {
+        // Needs a real engine, so only runs where one is configured
+        let Ok(path) = env::var(STOCKFISH_PATH_VAR) else {
+            eprintln!("{STOCKFISH_PATH_VAR} isn't set, skipping");
+            return;
+        };
+        let mut stockfish = Stockfish::with_path(path).unwrap();
+        // Castling both ways, an en passant capture and promotions with captures
+        let positions = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ];
+
+        for fen in positions {
+            let fen: Fen = fen.parse().unwrap();
+            let board: Chess = fen.clone().into_position(CastlingMode::Standard).unwrap();
+            assert_eq!(stockfish.perft(&fen, 3).unwrap(), shakmaty::perft(&board, 3), "{fen}");
+        }
+    }
+}
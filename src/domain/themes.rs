@@ -0,0 +1,222 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use shakmaty::attacks;
+use shakmaty::{Bitboard, Chess, Position, Rank, Role, Square};
+
+use crate::domain::notation::{self, NotationError};
+
+/// Value the king is given when comparing pieces, higher than any other piece
+const KING_VALUE: u8 = 100;
+
+/// Tactical motif a puzzle solution is built around
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PuzzleTheme {
+    /// A solver move attacks two valuable enemy pieces at once
+    Fork,
+    /// A solver move pins an enemy piece to a more valuable one behind it
+    Pin,
+    /// A solver move attacks a valuable enemy piece standing in front of a lesser one
+    Skewer,
+    /// The solution ends in mate against a king walled in on its back rank
+    BackRankMate,
+}
+
+impl Display for PuzzleTheme {
+    /// Writes the theme under its Lichess name, the one it is serialized as
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            PuzzleTheme::Fork => "fork",
+            PuzzleTheme::Pin => "pin",
+            PuzzleTheme::Skewer => "skewer",
+            PuzzleTheme::BackRankMate => "backRankMate",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Tags a puzzle solution with the motifs it contains
+///
+/// # Arguments
+/// * `start` - Position the solver starts from
+/// * `solution` - Solution moves in UCI notation, the solver's moves on even indices
+///
+/// # Returns
+/// Every motif found, each listed once in the order it first shows up
+pub fn detect_themes(
+    start: &Chess,
+    solution: &[String],
+) -> Result<Vec<PuzzleTheme>, NotationError> {
+    let mut board = start.clone();
+    let mut themes = Vec::new();
+
+    for (ply, uci) in solution.iter().enumerate() {
+        let mov = notation::play_uci(uci, &mut board)?;
+        if !ply.is_multiple_of(2) || mov.is_castle() {
+            continue;
+        }
+
+        let found = [
+            is_fork(&board, mov.to()).then_some(PuzzleTheme::Fork),
+            line_motif(&board, mov.to()),
+        ];
+        for theme in found.into_iter().flatten() {
+            if !themes.contains(&theme) {
+                themes.push(theme);
+            }
+        }
+    }
+
+    if is_back_rank_mate(&board) {
+        themes.push(PuzzleTheme::BackRankMate);
+    }
+
+    Ok(themes)
+}
+
+/// Tells whether the piece on `sq` attacks two or more enemy pieces it threatens to win
+fn is_fork(board: &Chess, sq: Square) -> bool {
+    let Some(piece) = board.board().piece_at(sq) else {
+        return false;
+    };
+    if piece.role == Role::King {
+        return false;
+    }
+
+    let targets = board.board().attacks_from(sq) & board.board().by_color(!piece.color);
+    targets
+        .into_iter()
+        .filter(|target| threatens(board, piece.role, *target))
+        .count()
+        >= 2
+}
+
+/// Tells whether attacking `target` with a piece of role `attacker` threatens
+/// to win something: the target is the king, is worth more than the attacker,
+/// or isn't defended at all. Pawns are never counted as targets
+fn threatens(board: &Chess, attacker: Role, target: Square) -> bool {
+    let Some(victim) = board.board().piece_at(target) else {
+        return false;
+    };
+    if victim.role == Role::Pawn {
+        return false;
+    }
+
+    let defenders = board
+        .board()
+        .attacks_to(target, victim.color, board.board().occupied());
+    victim.role == Role::King || value(victim.role) > value(attacker) || defenders.is_empty()
+}
+
+/// Finds a pin or skewer made by the slider on `sq`, looking through each
+/// enemy piece it attacks at the enemy piece standing right behind it
+fn line_motif(board: &Chess, sq: Square) -> Option<PuzzleTheme> {
+    let piece = board.board().piece_at(sq)?;
+    if !matches!(piece.role, Role::Bishop | Role::Rook | Role::Queen) {
+        return None;
+    }
+
+    let occupied = board.board().occupied();
+    let enemies = board.board().by_color(!piece.color);
+    let seen = attacks::attacks(sq, piece, occupied);
+
+    for front in seen & enemies {
+        let xray = attacks::attacks(sq, piece, occupied.without(front)).without(seen);
+        let Some(back) = (xray & enemies).first() else {
+            continue;
+        };
+        let front_value = value(board.board().role_at(front)?);
+        let back_role = board.board().role_at(back)?;
+
+        if value(back_role) > front_value {
+            return Some(PuzzleTheme::Pin);
+        }
+        if front_value > value(back_role) && back_role != Role::Pawn {
+            return Some(PuzzleTheme::Skewer);
+        }
+    }
+
+    None
+}
+
+/// Tells whether the side to move is mated on its back rank by a rook or
+/// queen, with every square in front of its king taken by its own pieces
+fn is_back_rank_mate(board: &Chess) -> bool {
+    if !board.is_checkmate() {
+        return false;
+    }
+
+    let mated = board.turn();
+    let Some(king) = board.board().king_of(mated) else {
+        return false;
+    };
+    let back_rank = mated.backrank();
+    if king.rank() != back_rank {
+        return false;
+    }
+
+    let checked_along_rank = board.checkers().into_iter().any(|checker| {
+        checker.rank() == back_rank
+            && matches!(
+                board.board().role_at(checker),
+                Some(Role::Rook | Role::Queen)
+            )
+    });
+    let in_front =
+        attacks::king_attacks(king) & Bitboard::from_rank(mated.relative_rank(Rank::Second));
+
+    checked_along_rank && in_front.without(board.board().by_color(mated)).is_empty()
+}
+
+/// Conventional material value of a piece
+fn value(role: Role) -> u8 {
+    match role {
+        Role::Pawn => 1,
+        Role::Knight | Role::Bishop => 3,
+        Role::Rook => 5,
+        Role::Queen => 9,
+        Role::King => KING_VALUE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn themes_of(fen: &str, solution: &[&str]) -> Vec<PuzzleTheme> {
+        let start = notation::board_from_fen(fen).unwrap();
+        let solution: Vec<String> = solution.iter().map(|uci| uci.to_string()).collect();
+        detect_themes(&start, &solution).unwrap()
+    }
+
+    #[test]
+    fn knight_forks_king_and_rook() {
+        let themes = themes_of("r3k3/8/8/1N6/8/8/8/4K3 w - - 0 1", &["b5c7"]);
+        assert_eq!(themes, [PuzzleTheme::Fork]);
+    }
+
+    #[test]
+    fn bishop_pins_knight_to_king() {
+        let themes = themes_of("4k3/8/2n5/8/8/8/8/4KB2 w - - 0 1", &["f1b5"]);
+        assert_eq!(themes, [PuzzleTheme::Pin]);
+    }
+
+    #[test]
+    fn rook_skewers_king_and_queen() {
+        let themes = themes_of("q3k3/8/8/8/8/8/8/4K2R w - - 0 1", &["h1h8"]);
+        assert_eq!(themes, [PuzzleTheme::Skewer]);
+    }
+
+    #[test]
+    fn rook_mates_on_the_back_rank() {
+        let themes = themes_of("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", &["a1a8"]);
+        assert_eq!(themes, [PuzzleTheme::BackRankMate]);
+    }
+
+    #[test]
+    fn quiet_moves_have_no_theme() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert!(themes_of(start, &["e2e4", "e7e5", "g1f3"]).is_empty());
+    }
+}
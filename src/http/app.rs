@@ -1,18 +1,155 @@
-use std::sync::{Arc, Mutex};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
 
-use axum::{routing::post, Router};
+use axum::{
+    Router,
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{get, post},
+};
 
-use crate::{common::config::Config, domain::stockfish::Stockfish};
+use crate::common::config::Config;
+use crate::domain::{cache::EvalCache, pool::StockfishPool};
 
-use super::handler::create_puzzle;
+use super::handler::{create_puzzle, create_puzzle_from_fen, create_puzzles_batch, validate_fen};
+use super::health::{health, ready};
+use super::maintenance::spawn_engine_recycler;
+use super::metrics::{Metrics, metrics};
+use super::middleware::request_id;
 
 #[derive(Clone)]
 pub struct AppState {
     pub conf: Config,
-    pub stockfish: Arc<Mutex<Stockfish>>,
+    pub engines: StockfishPool,
+    pub client: reqwest::Client,
+    /// How many times an engine has been respawned by the health check
+    pub engine_recycles: Arc<AtomicU64>,
+    pub metrics: Arc<Metrics>,
+    /// Engine results shared by every request, `None` when caching is disabled
+    pub eval_cache: Option<Arc<EvalCache>>,
 }
 
-pub fn app(conf: &Config, stockfish: Stockfish) -> Router {
-    let state = AppState { conf: conf.clone(), stockfish: Arc::new(Mutex::new(stockfish)) };
-    Router::new().route("/chessland/puzzler/generate", post(create_puzzle)).with_state(state)
+pub fn app(conf: &Config, engines: StockfishPool) -> Router {
+    let state = AppState {
+        conf: conf.clone(),
+        engines,
+        client: reqwest::Client::new(),
+        engine_recycles: Arc::new(AtomicU64::new(0)),
+        metrics: Arc::new(Metrics::default()),
+        eval_cache: NonZeroUsize::new(conf.eval_cache_size)
+            .map(|capacity| Arc::new(EvalCache::new(capacity))),
+    };
+    spawn_engine_recycler(state.clone());
+
+    Router::new()
+        .route("/chessland/puzzler/generate", post(create_puzzle))
+        .route(
+            "/chessland/puzzler/generate/batch",
+            post(create_puzzles_batch),
+        )
+        .route(
+            "/chessland/puzzler/generate/fen",
+            post(create_puzzle_from_fen),
+        )
+        .route("/chessland/puzzler/validate-fen", post(validate_fen))
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .route("/metrics", get(metrics))
+        .layer(DefaultBodyLimit::max(conf.max_body_bytes))
+        .layer(middleware::from_fn(request_id))
+        .with_state(state)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::domain::puzzle::tests::MATE_IN_TWO;
+    use crate::domain::stockfish::tests::fake_engine;
+
+    /// State of a server with the given engines and the example config
+    pub(crate) fn test_state(engines: StockfishPool) -> AppState {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "puzzler-test-{}-{}.toml",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, include_str!("../../config.example.toml")).unwrap();
+        let conf = Config::from_toml(path.to_str().unwrap()).unwrap();
+
+        AppState {
+            conf,
+            engines,
+            client: reqwest::Client::new(),
+            engine_recycles: Arc::new(AtomicU64::new(0)),
+            metrics: Arc::new(Metrics::default()),
+            eval_cache: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_refused() {
+        let mut conf = test_state(StockfishPool::new(Vec::new())).conf;
+        conf.max_body_bytes = 64;
+        let router = app(&conf, StockfishPool::new(vec![fake_engine("")]));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let moves = "e2e4 e7e5 ".repeat(10);
+        let response = reqwest::Client::new()
+            .post(format!("http://{addr}/chessland/puzzler/generate"))
+            .header("x-api-key", &conf.api_key)
+            .json(&serde_json::json!({ "PGN": moves }))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn health_answers_while_a_puzzle_is_generated() {
+        // A single engine taking its time over every search, on a runtime
+        // with a single thread, which generation mustn't hold up
+        let slow_engine = fake_engine(
+            r#"
+    eval) echo "Final evaluation       0.5 (white side)";;
+    go*)
+      sleep 0.1
+      echo "info depth 8 multipv 1 score mate 2 pv e2e8 a8e8 e1e8"
+      echo "bestmove e2e8";;"#,
+        );
+        let conf = test_state(StockfishPool::new(Vec::new())).conf;
+        let router = app(&conf, StockfishPool::new(vec![slow_engine]));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let generate = || {
+            let request = reqwest::Client::new()
+                .post(format!("http://{addr}/chessland/puzzler/generate/fen"))
+                .header("x-api-key", &conf.api_key)
+                .json(&serde_json::json!({ "fen": MATE_IN_TWO, "level": "hard" }))
+                .send();
+            tokio::spawn(request)
+        };
+        let first = generate();
+        let second = generate();
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+
+        let health = reqwest::get(format!("http://{addr}/health")).await.unwrap();
+        assert_eq!(health.status(), reqwest::StatusCode::OK);
+        assert!(!first.is_finished() || !second.is_finished());
+
+        for generation in [first, second] {
+            let response = generation.await.unwrap().unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+        }
+    }
 }
@@ -4,10 +4,14 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use serde_json::json;
 
+use crate::domain::puzzle::GenerationError;
+
 pub enum HTTPError {
     ApiKeyMissing,
     ApiKeyInvalid,
     InvalidBody(String),
+    /// Well-formed request that no puzzle could be generated for
+    Unprocessable(String),
     ServerError(String),
 }
 
@@ -19,6 +23,7 @@ impl Display for HTTPError {
                 write!(f, "api key mismatch, probably contains invalid characters")
             }
             HTTPError::InvalidBody(e) => write!(f, "{e}"),
+            HTTPError::Unprocessable(e) => write!(f, "{e}"),
             HTTPError::ServerError(e) => write!(f, "{e}"),
         }
     }
@@ -26,6 +31,13 @@ impl Display for HTTPError {
 
 impl IntoResponse for HTTPError {
     fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            HTTPError::ApiKeyMissing | HTTPError::ApiKeyInvalid => StatusCode::UNAUTHORIZED,
+            HTTPError::InvalidBody(_) => StatusCode::BAD_REQUEST,
+            HTTPError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            HTTPError::ServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
         let body = match self {
             HTTPError::ApiKeyMissing => json!({
                 "error": "api key is missing"
@@ -39,12 +51,107 @@ impl IntoResponse for HTTPError {
                 "error": e
             })
             .to_string(),
+            HTTPError::Unprocessable(e) => json!({
+                "error": e
+            })
+            .to_string(),
             HTTPError::ServerError(e) => json!({
                 "error": e
             })
             .to_string(),
         };
 
-        (StatusCode::BAD_REQUEST, body).into_response()
+        (status, body).into_response()
+    }
+}
+
+impl From<GenerationError> for HTTPError {
+    fn from(err: GenerationError) -> Self {
+        match err {
+            GenerationError::InvalidPgn(_)
+            | GenerationError::InvalidPosition(_)
+            | GenerationError::GameTooLong(..)
+            | GenerationError::InvalidWindow(_) => {
+                HTTPError::InvalidBody(err.to_string())
+            }
+            GenerationError::GameTooShort(_) | GenerationError::NoTacticFound(_) => {
+                HTTPError::Unprocessable(err.to_string())
+            }
+            GenerationError::EngineError(_) | GenerationError::Internal(_) => {
+                HTTPError::ServerError(err.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+
+    async fn respond(err: HTTPError) -> (StatusCode, serde_json::Value) {
+        let response = err.into_response();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn api_key_errors_are_unauthorized() {
+        let (status, body) = respond(HTTPError::ApiKeyMissing).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "api key is missing");
+
+        let (status, _) = respond(HTTPError::ApiKeyInvalid).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn each_variant_has_its_status() {
+        let cases = [
+            (
+                HTTPError::InvalidBody("bad".into()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                HTTPError::Unprocessable("no tactic".into()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                HTTPError::ServerError("engine died".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (err, expected) in cases {
+            let message = err.to_string();
+            let (status, body) = respond(err).await;
+            assert_eq!(status, expected);
+            assert_eq!(body["error"], message);
+        }
+    }
+
+    #[test]
+    fn generation_errors_map_to_the_caller_or_the_server() {
+        assert!(matches!(
+            HTTPError::from(GenerationError::GameTooLong(700, 600)),
+            HTTPError::InvalidBody(_)
+        ));
+        assert!(matches!(
+            HTTPError::from(GenerationError::InvalidPosition("bad fen".into())),
+            HTTPError::InvalidBody(_)
+        ));
+        assert!(matches!(
+            HTTPError::from(GenerationError::NoTacticFound("quiet game".into())),
+            HTTPError::Unprocessable(_)
+        ));
+        assert!(matches!(
+            HTTPError::from(GenerationError::GameTooShort(3)),
+            HTTPError::Unprocessable(_)
+        ));
+        assert!(matches!(
+            HTTPError::from(GenerationError::EngineError("eof".into())),
+            HTTPError::ServerError(_)
+        ));
     }
 }
@@ -1,45 +1,726 @@
-use crate::{domain::puzzle::Puzzle, http::app::AppState};
+use std::ops::RangeInclusive;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
 
-use axum::{Json, extract::State, http::HeaderMap};
-use serde_json::Value;
-use tracing::info;
+use crate::{
+    domain::puzzle::{
+        GenerationError, GenerationMode, GenerationOptions, InvalidLevelError, Puzzle, PuzzleLevel,
+    },
+    http::app::AppState,
+};
 
-use crate::{common::config::Config, domain::puzzle};
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use shakmaty::Position;
+use subtle::ConstantTimeEq;
+use tracing::{Instrument, Span, info, info_span, warn};
+
+use crate::{
+    common::config::Config,
+    domain::{notation, puzzle, puzzle_set::PuzzleSet, stockfish::Stockfish},
+};
 
 use super::error::HTTPError;
+use super::metrics::Metrics;
+use super::publish;
+
+/// Search depths a request may ask for
+const DEPTH_RANGE: RangeInclusive<u64> = 1..=30;
+
+/// Fields the game's moves are looked for in, in order
+const PGN_FIELDS: [&str; 3] = ["PGN", "pgn", "moves"];
+
+/// Number of puzzles a request may ask for from one game
+const COUNT_RANGE: RangeInclusive<u64> = 1..=10;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct GenerateParams {
+    /// Respond with only the start FEN and the solution
+    minimal: bool,
+}
 
 pub async fn create_puzzle(
     State(state): State<AppState>,
+    Query(params): Query<GenerateParams>,
     headers: HeaderMap,
     Json(body): Json<Value>,
-) -> Result<Json<Puzzle>, HTTPError> {
+) -> Result<Response, HTTPError> {
     info!("create puzzle endpoint is invoked");
+    state.metrics.requests.fetch_add(1, Ordering::Relaxed);
     validate_headers(&state.conf, headers)?;
-    let raw_moves = extract_payload(&body)?;
-    let maybe_puzzle = puzzle::generate_puzzle_by_position_analysis(
-        raw_moves,
-        &mut state.stockfish.lock().unwrap(),
-    );
+    let raw_moves = extract_payload(&state.conf, &body)?.to_string();
+    let count = extract_count(&body)?;
+    let options = extract_options(&state, &body)?;
+    let publish = extract_publish(&state.conf, &body)?;
+
+    let span = info_span!("generate", pgn_len = raw_moves.len());
+    async move {
+        let started = Instant::now();
+        let response = match count {
+            Some(count) => with_engine(&state, move |stockfish| {
+                stockfish.record_request();
+                puzzle::generate_puzzles(&raw_moves, count, &options, stockfish)
+            })
+            .await
+            .and_then(|maybe_puzzles| {
+                count_parse_failure(&state.metrics, &maybe_puzzles);
+                puzzles_response(&state, &params, publish, maybe_puzzles?)
+            }),
+            None => with_engine(&state, move |stockfish| {
+                stockfish.record_request();
+                puzzle::generate_puzzle_by_position_analysis(&raw_moves, &options, stockfish)
+            })
+            .await
+            .and_then(|maybe_puzzle| {
+                count_parse_failure(&state.metrics, &maybe_puzzle);
+                puzzle_response(&state, &params, publish, maybe_puzzle?)
+            }),
+        };
+        let elapsed = started.elapsed();
+        state.metrics.observe_latency(elapsed);
+        info!(
+            elapsed_ms = elapsed.as_millis() as u64,
+            "puzzle generation finished"
+        );
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+/// Generates a puzzle out of a single position given as a FEN
+pub async fn create_puzzle_from_fen(
+    State(state): State<AppState>,
+    Query(params): Query<GenerateParams>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Result<Response, HTTPError> {
+    info!("create puzzle from fen endpoint is invoked");
+    state.metrics.requests.fetch_add(1, Ordering::Relaxed);
+    validate_headers(&state.conf, headers)?;
+    let fen = body["fen"]
+        .as_str()
+        .ok_or(HTTPError::InvalidBody("fen must be a string".to_string()))?
+        .to_string();
+    let options = extract_options(&state, &body)?;
+    let publish = extract_publish(&state.conf, &body)?;
+
+    let started = Instant::now();
+    let maybe_puzzle = with_engine(&state, move |stockfish| {
+        stockfish.record_request();
+        puzzle::generate_puzzle_from_fen(&fen, &options, stockfish)
+    })
+    .await;
+    state.metrics.observe_latency(started.elapsed());
+
+    let maybe_puzzle = maybe_puzzle?;
+    count_parse_failure(&state.metrics, &maybe_puzzle);
+    puzzle_response(&state, &params, publish, maybe_puzzle?)
+}
 
-    match maybe_puzzle {
-        Ok(puzzle) => {
-            info!("generated and returning puzzle");
-            Ok(Json(puzzle))
+/// Runs `work` with an engine checked out of the pool, on a blocking thread
+/// so neither waiting for an engine nor the analysis stalls the async runtime
+///
+/// # Returns
+/// What `work` returned, or a server error if it panicked
+async fn with_engine<T, F>(state: &AppState, work: F) -> Result<T, HTTPError>
+where
+    F: FnOnce(&mut Stockfish) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let engines = state.engines.clone();
+    let metrics = state.metrics.clone();
+    let span = Span::current();
+
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let mut stockfish = engines.acquire();
+        let timeouts = stockfish.timeouts();
+        let outcome = work(&mut stockfish);
+        metrics
+            .engine_timeouts
+            .fetch_add(stockfish.timeouts() - timeouts, Ordering::Relaxed);
+        outcome
+    })
+    .await
+    .map_err(|e| HTTPError::ServerError(format!("puzzle generation failed: {e}")))
+}
+
+/// Counts a generation that failed because the moves or the position
+/// couldn't be parsed
+fn count_parse_failure<T>(metrics: &Metrics, outcome: &Result<T, GenerationError>) {
+    if let Err(GenerationError::InvalidPgn(_) | GenerationError::InvalidPosition(_)) = outcome {
+        metrics.parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Responds with a single generated puzzle, sending it to Chessland too
+/// when `publish` is set
+fn puzzle_response(
+    state: &AppState,
+    params: &GenerateParams,
+    publish: bool,
+    puzzle: Puzzle,
+) -> Result<Response, HTTPError> {
+    info!("generated and returning puzzle");
+    if publish {
+        spawn_publish(state, &puzzle);
+    }
+    if params.minimal {
+        let minimal = puzzle
+            .to_minimal()
+            .map_err(|e| HTTPError::ServerError(e.to_string()))?;
+        return Ok(Json(minimal).into_response());
+    }
+    Ok(Json(puzzle).into_response())
+}
+
+/// Responds with several puzzles generated from the same game, sending them
+/// to Chessland too when `publish` is set
+fn puzzles_response(
+    state: &AppState,
+    params: &GenerateParams,
+    publish: bool,
+    puzzles: Vec<Puzzle>,
+) -> Result<Response, HTTPError> {
+    info!("generated and returning {} puzzles", puzzles.len());
+    if publish {
+        for puzzle in &puzzles {
+            spawn_publish(state, puzzle);
         }
-        Err(e) => Err(HTTPError::InvalidBody(e.to_string())),
     }
+    if params.minimal {
+        let minimal = puzzles
+            .iter()
+            .map(Puzzle::to_minimal)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| HTTPError::ServerError(e.to_string()))?;
+        return Ok(Json(minimal).into_response());
+    }
+    Ok(Json(puzzles).into_response())
 }
 
+/// Generates one puzzle per submitted game, carrying on past games that fail
+pub async fn create_puzzles_batch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Result<Json<Value>, HTTPError> {
+    info!("batch generate endpoint is invoked");
+    state.metrics.requests.fetch_add(1, Ordering::Relaxed);
+    validate_headers(&state.conf, headers)?;
+    let games: Vec<String> = extract_games(&body)?
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    if games.len() > state.conf.max_batch_size {
+        return Err(HTTPError::InvalidBody(format!(
+            "batch of {} games is over the limit of {}",
+            games.len(),
+            state.conf.max_batch_size
+        )));
+    }
+    let options = extract_options(&state, &body)?;
+    let publish = extract_publish(&state.conf, &body)?;
+
+    let metrics = state.metrics.clone();
+    let started = Instant::now();
+    let generated = with_engine(&state, move |stockfish| {
+        let mut puzzles = Vec::new();
+        let mut errors = Vec::new();
+
+        for (index, moves) in games.iter().enumerate() {
+            stockfish.record_request();
+            let outcome = puzzle::generate_puzzle_by_position_analysis(moves, &options, stockfish);
+            count_parse_failure(&metrics, &outcome);
+            match outcome {
+                Ok(puzzle) => puzzles.push(puzzle),
+                Err(e) => {
+                    warn!("batch game {index} yielded no puzzle: {e}");
+                    errors.push(json!({ "index": index, "message": e.to_string() }));
+                }
+            }
+        }
+        (PuzzleSet::from(puzzles), errors)
+    })
+    .await;
+    state.metrics.observe_latency(started.elapsed());
+    let (puzzles, errors) = generated?;
+
+    info!(
+        "generated {} puzzles from a batch, {} games failed",
+        puzzles.len(),
+        errors.len()
+    );
+    if publish {
+        for puzzle in puzzles.puzzles() {
+            spawn_publish(&state, puzzle);
+        }
+    }
+
+    Ok(Json(json!({ "puzzles": puzzles, "errors": errors })))
+}
+
+pub async fn validate_fen(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Result<Json<Value>, HTTPError> {
+    info!("validate fen endpoint is invoked");
+    validate_headers(&state.conf, headers)?;
+    let fen = body["FEN"]
+        .as_str()
+        .ok_or(HTTPError::InvalidBody("invalid json".to_string()))?;
+
+    let board =
+        notation::board_from_fen(fen).map_err(|e| HTTPError::Unprocessable(e.to_string()))?;
+
+    Ok(Json(json!({
+        "valid": true,
+        "sideToMove": board.turn().fold_wb("white", "black"),
+        "legalMoves": board.legal_moves().len(),
+    })))
+}
+
+fn spawn_publish(state: &AppState, puzzle: &Puzzle) {
+    let payload = match serde_json::to_value(puzzle) {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn!("can't serialize puzzle for publishing: {e}");
+            return;
+        }
+    };
+
+    let client = state.client.clone();
+    let conf = state.conf.clone();
+    tokio::spawn(
+        async move { publish::publish_puzzle(&client, &conf, &payload).await }.in_current_span(),
+    );
+}
+
+/// Checks the `x-api-key` header, comparing it in constant time so response
+/// times don't give away how much of a guessed key is right
 fn validate_headers(conf: &Config, headers: HeaderMap) -> Result<(), HTTPError> {
     match headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
-        Some(key) if key == conf.api_key => Ok(()),
+        Some(key) if bool::from(key.as_bytes().ct_eq(conf.api_key.as_bytes())) => Ok(()),
         Some(_) => Err(HTTPError::ApiKeyInvalid),
         None => Err(HTTPError::ApiKeyMissing),
     }
 }
 
-fn extract_payload(json: &Value) -> Result<&str, HTTPError> {
-    json["PGN"]
+/// Reads the game's moves from the configured field, or else the first of
+/// [`PGN_FIELDS`] present
+fn extract_payload<'a>(conf: &Config, json: &'a Value) -> Result<&'a str, HTTPError> {
+    let fields: Vec<&str> = conf
+        .pgn_field
+        .as_deref()
+        .into_iter()
+        .chain(PGN_FIELDS)
+        .collect();
+
+    fields
+        .iter()
+        .find_map(|field| json[field].as_str())
+        .ok_or_else(|| {
+            HTTPError::InvalidBody(format!(
+                "expected the moves as a string in one of the fields {}",
+                fields.join(", ")
+            ))
+        })
+}
+
+/// Reads the optional RNG seed that makes the generated puzzle reproducible
+fn extract_seed(json: &Value) -> Result<Option<u64>, HTTPError> {
+    let seed = &json["seed"];
+    if seed.is_null() {
+        return Ok(None);
+    }
+
+    seed.as_u64().map(Some).ok_or(HTTPError::InvalidBody(
+        "seed must be a non-negative integer".to_string(),
+    ))
+}
+
+/// Builds the generation options out of the optional tuning fields of the body
+fn extract_options(state: &AppState, json: &Value) -> Result<GenerationOptions, HTTPError> {
+    let conf = &state.conf;
+    let level = extract_level(json)?;
+
+    Ok(GenerationOptions {
+        mode: extract_mode(json)?,
+        depth: extract_depth(json)?,
+        seed: extract_seed(json)?,
+        from_ply: extract_ply(json, "from_ply")?,
+        to_ply: extract_ply(json, "to_ply")?,
+        debug: extract_debug(json)?,
+        max_plies: Some(conf.max_game_plies),
+        tablebases: conf.syzygy_path.is_some(),
+        cache: state.eval_cache.clone(),
+        ..GenerationOptions::for_level(level)
+    })
+}
+
+/// Reads the optional `publish` flag, asking for the generated puzzles to be
+/// sent to Chessland; the config decides when it's missing
+fn extract_publish(conf: &Config, json: &Value) -> Result<bool, HTTPError> {
+    let publish = &json["publish"];
+    if publish.is_null() {
+        return Ok(conf.publish_puzzles);
+    }
+
+    publish.as_bool().ok_or(HTTPError::InvalidBody(
+        "publish must be a boolean".to_string(),
+    ))
+}
+
+/// Reads the optional `debug` flag, asking for the analysis of every
+/// candidate position to be included in the puzzle
+fn extract_debug(json: &Value) -> Result<bool, HTTPError> {
+    let debug = &json["debug"];
+    if debug.is_null() {
+        return Ok(false);
+    }
+
+    debug.as_bool().ok_or(HTTPError::InvalidBody(
+        "debug must be a boolean".to_string(),
+    ))
+}
+
+/// Reads the games of a batch, each a sequence of moves like the `PGN` field
+fn extract_games(json: &Value) -> Result<Vec<&str>, HTTPError> {
+    json["games"]
+        .as_array()
+        .ok_or(HTTPError::InvalidBody("games must be an array".to_string()))?
+        .iter()
+        .map(|game| {
+            game.as_str().ok_or(HTTPError::InvalidBody(
+                "every game must be a string".to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Reads the optional puzzle level, medium when missing
+fn extract_level(json: &Value) -> Result<PuzzleLevel, HTTPError> {
+    let level = &json["level"];
+    if level.is_null() {
+        return Ok(PuzzleLevel::default());
+    }
+
+    level
         .as_str()
-        .ok_or(HTTPError::InvalidBody("invalid json".to_string()))
+        .ok_or(HTTPError::InvalidBody("level must be a string".to_string()))?
+        .parse()
+        .map_err(|e: InvalidLevelError| HTTPError::InvalidBody(e.to_string()))
+}
+
+/// Reads the optional generation mode, any tactical position by default
+fn extract_mode(json: &Value) -> Result<GenerationMode, HTTPError> {
+    let mode = &json["mode"];
+    if mode.is_null() {
+        return Ok(GenerationMode::default());
+    }
+
+    GenerationMode::deserialize(mode).map_err(|_| {
+        HTTPError::InvalidBody("mode must be one of best_move or punish_blunder".to_string())
+    })
+}
+
+/// Reads the optional number of puzzles to generate, `None` asking for a
+/// single puzzle rather than a list
+fn extract_count(json: &Value) -> Result<Option<usize>, HTTPError> {
+    let count = &json["count"];
+    if count.is_null() {
+        return Ok(None);
+    }
+
+    match count.as_u64() {
+        Some(count) if COUNT_RANGE.contains(&count) => Ok(Some(count as usize)),
+        _ => Err(HTTPError::InvalidBody(format!(
+            "count must be an integer between {} and {}",
+            COUNT_RANGE.start(),
+            COUNT_RANGE.end()
+        ))),
+    }
+}
+
+/// Reads an optional ply bound of the window candidates are taken from
+///
+/// # Arguments
+/// * `field` - Name of the body field, `from_ply` or `to_ply`
+fn extract_ply(json: &Value, field: &str) -> Result<Option<usize>, HTTPError> {
+    let ply = &json[field];
+    if ply.is_null() {
+        return Ok(None);
+    }
+
+    ply.as_u64()
+        .map(|ply| Some(ply as usize))
+        .ok_or_else(|| HTTPError::InvalidBody(format!("{field} must be a non-negative integer")))
+}
+
+/// Reads the optional search depth, `None` leaving it to the puzzle level
+fn extract_depth(json: &Value) -> Result<Option<u8>, HTTPError> {
+    let depth = &json["depth"];
+    if depth.is_null() {
+        return Ok(None);
+    }
+
+    match depth.as_u64() {
+        Some(depth) if DEPTH_RANGE.contains(&depth) => Ok(Some(depth as u8)),
+        _ => Err(HTTPError::InvalidBody(format!(
+            "depth must be an integer between {} and {}",
+            DEPTH_RANGE.start(),
+            DEPTH_RANGE.end()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+    use crate::domain::pool::StockfishPool;
+    use crate::domain::puzzle::tests::{MATE_IN_TWO, mate_in_two_engine};
+    use crate::domain::stockfish::tests::fake_engine;
+    use crate::http::app::tests::test_state;
+
+    fn api_key_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "change-me".parse().unwrap());
+        headers
+    }
+
+    /// Reads a line of the rendered metrics, e.g. `puzzler_requests_total 1`
+    fn metric(state: &AppState, name: &str) -> String {
+        let rendered = state.metrics.render(0, None);
+        rendered
+            .lines()
+            .find(|line| line.starts_with(&format!("{name} ")))
+            .unwrap_or_else(|| panic!("no {name} in {rendered}"))
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn fen_requests_are_counted() {
+        let state = test_state(StockfishPool::new(vec![fake_engine("")]));
+
+        let response = create_puzzle_from_fen(
+            State(state.clone()),
+            Query(GenerateParams::default()),
+            api_key_headers(),
+            Json(json!({ "fen": "not a position" })),
+        )
+        .await;
+
+        assert!(matches!(response, Err(HTTPError::InvalidBody(_))));
+        assert_eq!(
+            metric(&state, "puzzler_requests_total"),
+            "puzzler_requests_total 1"
+        );
+        assert_eq!(
+            metric(&state, "puzzler_parse_failures_total"),
+            "puzzler_parse_failures_total 1"
+        );
+        assert_eq!(
+            metric(&state, "puzzler_generation_seconds_count"),
+            "puzzler_generation_seconds_count 1"
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_requests_are_counted() {
+        let state = test_state(StockfishPool::new(vec![fake_engine("")]));
+
+        let Ok(Json(response)) = create_puzzles_batch(
+            State(state.clone()),
+            api_key_headers(),
+            Json(json!({ "games": ["e2e4 e7e5 zz", "e2e4 e7e5 e1e8"] })),
+        )
+        .await
+        else {
+            panic!("the batch itself should go through");
+        };
+
+        assert_eq!(response["errors"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            metric(&state, "puzzler_requests_total"),
+            "puzzler_requests_total 1"
+        );
+        assert_eq!(
+            metric(&state, "puzzler_parse_failures_total"),
+            "puzzler_parse_failures_total 2"
+        );
+        assert_eq!(
+            metric(&state, "puzzler_generation_seconds_count"),
+            "puzzler_generation_seconds_count 1"
+        );
+    }
+
+    #[test]
+    fn moves_are_read_from_any_alias() {
+        let state = test_state(StockfishPool::new(vec![fake_engine("")]));
+        for field in PGN_FIELDS {
+            let body = json!({ field: "e2e4 e7e5" });
+            assert!(matches!(
+                extract_payload(&state.conf, &body),
+                Ok("e2e4 e7e5")
+            ));
+        }
+
+        let body = json!({ "moves": "d2d4", "PGN": "e2e4" });
+        assert!(matches!(extract_payload(&state.conf, &body), Ok("e2e4")));
+    }
+
+    #[test]
+    fn configured_field_is_tried_first() {
+        let mut state = test_state(StockfishPool::new(vec![fake_engine("")]));
+        state.conf.pgn_field = Some("game".to_string());
+
+        let body = json!({ "PGN": "e2e4", "game": "d2d4" });
+        assert!(matches!(extract_payload(&state.conf, &body), Ok("d2d4")));
+    }
+
+    #[test]
+    fn body_without_moves_names_the_fields() {
+        let state = test_state(StockfishPool::new(vec![fake_engine("")]));
+
+        let Err(HTTPError::InvalidBody(message)) = extract_payload(&state.conf, &json!({})) else {
+            panic!("a body without moves was accepted");
+        };
+        assert_eq!(
+            message,
+            "expected the moves as a string in one of the fields PGN, pgn, moves"
+        );
+        let body = json!({ "PGN": ["e2e4"] });
+        assert!(extract_payload(&state.conf, &body).is_err());
+    }
+
+    #[test]
+    fn api_key_is_checked() {
+        let state = test_state(StockfishPool::new(vec![fake_engine("")]));
+        assert!(validate_headers(&state.conf, api_key_headers()).is_ok());
+        assert!(matches!(
+            validate_headers(&state.conf, HeaderMap::new()),
+            Err(HTTPError::ApiKeyMissing)
+        ));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "change-it".parse().unwrap());
+        assert!(matches!(
+            validate_headers(&state.conf, headers),
+            Err(HTTPError::ApiKeyInvalid)
+        ));
+    }
+
+    #[test]
+    fn tuning_fields_are_bounded() {
+        assert!(matches!(extract_depth(&json!({})), Ok(None)));
+        assert!(matches!(
+            extract_depth(&json!({ "depth": 30 })),
+            Ok(Some(30))
+        ));
+        assert!(extract_depth(&json!({ "depth": 31 })).is_err());
+        assert!(extract_depth(&json!({ "depth": 0 })).is_err());
+        assert!(matches!(
+            extract_count(&json!({ "count": 10 })),
+            Ok(Some(10))
+        ));
+        assert!(extract_count(&json!({ "count": 11 })).is_err());
+        assert!(extract_seed(&json!({ "seed": -1 })).is_err());
+        assert!(extract_mode(&json!({ "mode": "mate" })).is_err());
+    }
+
+    #[tokio::test]
+    async fn games_over_the_ply_limit_are_rejected() {
+        let mut state = test_state(StockfishPool::new(vec![fake_engine("")]));
+        state.conf.max_game_plies = 4;
+
+        let response = create_puzzle(
+            State(state),
+            Query(GenerateParams::default()),
+            api_key_headers(),
+            Json(json!({ "PGN": "e2e4 e7e5 g1f3 b8c6 f1b5" })),
+        )
+        .await;
+
+        let Err(HTTPError::InvalidBody(message)) = response else {
+            panic!("a game over the ply limit was accepted");
+        };
+        assert_eq!(message, "game has 5 plies, more than the limit of 4");
+    }
+
+    #[tokio::test]
+    async fn batches_over_the_limit_are_rejected() {
+        let mut state = test_state(StockfishPool::new(vec![fake_engine("")]));
+        state.conf.max_batch_size = 1;
+
+        let response = create_puzzles_batch(
+            State(state),
+            api_key_headers(),
+            Json(json!({ "games": ["e2e4 e7e5", "d2d4 d7d5"] })),
+        )
+        .await;
+
+        let Err(HTTPError::InvalidBody(message)) = response else {
+            panic!("a batch over the limit was accepted");
+        };
+        assert_eq!(message, "batch of 2 games is over the limit of 1");
+    }
+
+    #[tokio::test]
+    async fn puzzle_is_generated_from_a_position() {
+        let state = test_state(StockfishPool::new(vec![mate_in_two_engine()]));
+
+        let response = create_puzzle_from_fen(
+            State(state.clone()),
+            Query(GenerateParams::default()),
+            api_key_headers(),
+            Json(json!({ "fen": MATE_IN_TWO, "level": "hard" })),
+        )
+        .await;
+
+        let Ok(response) = response else {
+            panic!("no puzzle was generated from a mate in two");
+        };
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let puzzle: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(puzzle["startFen"], MATE_IN_TWO);
+        assert_eq!(
+            metric(&state, "puzzler_parse_failures_total"),
+            "puzzler_parse_failures_total 0"
+        );
+    }
+
+    #[tokio::test]
+    async fn positions_are_validated() {
+        let state = test_state(StockfishPool::new(vec![fake_engine("")]));
+
+        let Ok(Json(valid)) = validate_fen(
+            State(state.clone()),
+            api_key_headers(),
+            Json(json!({ "FEN": "r5k1/5ppp/8/8/8/8/4RPPP/4R1K1 w - - 0 1" })),
+        )
+        .await
+        else {
+            panic!("a legal position was rejected");
+        };
+        assert_eq!(valid["sideToMove"], "white");
+
+        let invalid = validate_fen(
+            State(state),
+            api_key_headers(),
+            Json(json!({ "FEN": "8/8/8/8/8/8/8/8 w - - 0 1" })),
+        )
+        .await;
+        assert!(matches!(invalid, Err(HTTPError::Unprocessable(_))));
+    }
 }
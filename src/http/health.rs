@@ -0,0 +1,87 @@
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use tracing::{error, warn};
+
+use super::app::AppState;
+
+/// Liveness probe, answering as long as the server itself runs
+pub async fn health() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness probe, answering once an idle engine replies to `isready`
+///
+/// An engine that doesn't reply makes the service unavailable, so load
+/// balancers stop routing requests to it. So do engines all busy with
+/// requests, none being left to check or to take another request.
+pub async fn ready(State(state): State<AppState>) -> Response {
+    let check = move || match state.engines.try_acquire() {
+        Some(mut engine) => engine
+            .is_ready()
+            .map_err(|e| format!("engine is unresponsive: {e}")),
+        None => Err("every engine is busy".to_string()),
+    };
+
+    let outcome = match tokio::task::spawn_blocking(check).await {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            error!("readiness check panicked: {e}");
+            Err("readiness check failed".to_string())
+        }
+    };
+
+    match outcome {
+        Ok(()) => Json(json!({ "status": "ready" })).into_response(),
+        Err(reason) => {
+            warn!("not ready: {reason}");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "status": "unavailable", "error": reason })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::domain::pool::StockfishPool;
+    use crate::domain::stockfish::tests::fake_engine;
+    use crate::http::app::tests::test_state;
+
+    #[tokio::test]
+    async fn busy_engines_are_unavailable() {
+        let state = test_state(StockfishPool::new(vec![fake_engine("")]));
+
+        assert_eq!(ready(State(state.clone())).await.status(), StatusCode::OK);
+
+        let busy = state.engines.acquire();
+        let response = ready(State(state.clone())).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        drop(busy);
+        assert_eq!(ready(State(state.clone())).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unresponsive_engine_is_unavailable() {
+        // Only answers the `isready` of the handshake
+        let mut engine = fake_engine(
+            r#"
+    isready) n=$((n+1)); [ "$n" -le 1 ] && echo "readyok";;"#,
+        );
+        engine.set_read_timeout(Some(Duration::from_millis(200)));
+        let state = test_state(StockfishPool::new(vec![engine]));
+
+        let response = ready(State(state)).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tracing::{error, info};
+
+use crate::domain::stockfish::Stockfish;
+
+use super::app::AppState;
+
+/// Periodically checks the idle engines and respawns those that stop
+/// answering `isready` or have served more requests than configured
+pub fn spawn_engine_recycler(state: AppState) {
+    let interval = Duration::from_secs(state.conf.engine_check_interval_secs.max(1));
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately, the engine was just spawned
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            let state = state.clone();
+            let check = move || state.engines.for_each_idle(|engine| check_engine(&state, engine));
+            if let Err(e) = tokio::task::spawn_blocking(check).await {
+                error!("engine health check panicked: {e}");
+            }
+        }
+    });
+}
+
+fn check_engine(state: &AppState, stockfish: &mut Stockfish) {
+    let reason = match stockfish.is_ready() {
+        Err(e) => format!("engine is unresponsive: {e}"),
+        Ok(()) => match state.conf.engine_max_requests {
+            Some(max) if stockfish.served() >= max => {
+                format!("engine served {} requests", stockfish.served())
+            }
+            _ => return,
+        },
+    };
+
+    info!("recycling engine, {reason}");
+    match Stockfish::from_config(&state.conf) {
+        Ok(fresh) => {
+            *stockfish = fresh;
+            let recycles = state.engine_recycles.fetch_add(1, Ordering::Relaxed) + 1;
+            info!("engine respawned, {recycles} recycles so far");
+        }
+        Err(e) => error!("can't respawn engine: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::pool::StockfishPool;
+    use crate::domain::stockfish::tests::fake_engine;
+    use crate::http::app::tests::test_state;
+
+    #[test]
+    fn healthy_engine_under_the_request_limit_is_kept() {
+        let mut state = test_state(StockfishPool::new(Vec::new()));
+        state.conf.engine_max_requests = Some(2);
+        let mut engine = fake_engine("");
+        engine.record_request();
+
+        check_engine(&state, &mut engine);
+
+        assert_eq!(engine.served(), 1);
+        assert_eq!(state.engine_recycles.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+
+use crate::domain::cache::EvalCache;
+
+use super::app::AppState;
+
+/// Upper bounds in seconds of the generation latency histogram buckets
+const LATENCY_BUCKETS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Content type of the Prometheus text exposition format
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Counters describing the load the service is under
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Puzzle generation requests received
+    pub requests: AtomicU64,
+    /// Requests whose moves couldn't be parsed
+    pub parse_failures: AtomicU64,
+    /// Reads that timed out waiting for an engine
+    pub engine_timeouts: AtomicU64,
+    latency: Histogram,
+}
+
+/// Latency histogram with the fixed [`LATENCY_BUCKETS`]
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulative, the last one past every bound
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Metrics {
+    /// Records how long generating a response took
+    pub fn observe_latency(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        let latency = &self.latency;
+        latency.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        latency
+            .sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        latency.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Writes the metrics in the Prometheus text format
+    ///
+    /// # Arguments
+    /// * `engine_recycles` - Times an engine has been respawned by the health check
+    /// * `cache` - Shared evaluation cache, if enabled
+    pub fn render(&self, engine_recycles: u64, cache: Option<&EvalCache>) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "puzzler_requests_total",
+                "Puzzle generation requests received",
+                self.requests.load(Ordering::Relaxed),
+            ),
+            (
+                "puzzler_parse_failures_total",
+                "Requests whose moves couldn't be parsed",
+                self.parse_failures.load(Ordering::Relaxed),
+            ),
+            (
+                "puzzler_engine_timeouts_total",
+                "Reads that timed out waiting for an engine",
+                self.engine_timeouts.load(Ordering::Relaxed),
+            ),
+            (
+                "puzzler_engine_recycles_total",
+                "Engines respawned by the health check",
+                engine_recycles,
+            ),
+            (
+                "puzzler_eval_cache_hits_total",
+                "Engine results answered from the evaluation cache",
+                cache.map_or(0, EvalCache::hits),
+            ),
+            (
+                "puzzler_eval_cache_misses_total",
+                "Engine results missing from the evaluation cache",
+                cache.map_or(0, EvalCache::misses),
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        let name = "puzzler_generation_seconds";
+        let latency = &self.latency;
+        let _ = writeln!(out, "# HELP {name} Time taken to generate a response");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&latency.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let total = latency.count.load(Ordering::Relaxed);
+        let sum = latency.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {total}");
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {total}");
+
+        out
+    }
+}
+
+/// Exposes the metrics for Prometheus to scrape
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    let recycles = state.engine_recycles.load(Ordering::Relaxed);
+    (
+        [(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)],
+        state.metrics.render(recycles, state.eval_cache.as_deref()),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+    use crate::domain::pool::StockfishPool;
+    use crate::domain::stockfish::tests::fake_engine;
+    use crate::http::app::tests::test_state;
+
+    #[test]
+    fn observations_fill_the_cumulative_buckets() {
+        let metrics = Metrics::default();
+        metrics.observe_latency(Duration::from_millis(300));
+        metrics.observe_latency(Duration::from_secs(90));
+
+        let rendered = metrics.render(2, None);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(lines.contains(&"puzzler_generation_seconds_bucket{le=\"0.25\"} 0"));
+        assert!(lines.contains(&"puzzler_generation_seconds_bucket{le=\"0.5\"} 1"));
+        assert!(lines.contains(&"puzzler_generation_seconds_bucket{le=\"60\"} 1"));
+        assert!(lines.contains(&"puzzler_generation_seconds_bucket{le=\"+Inf\"} 2"));
+        assert!(lines.contains(&"puzzler_generation_seconds_sum 90.3"));
+        assert!(lines.contains(&"puzzler_generation_seconds_count 2"));
+        assert!(lines.contains(&"puzzler_engine_recycles_total 2"));
+        assert!(lines.contains(&"# TYPE puzzler_requests_total counter"));
+    }
+
+    #[tokio::test]
+    async fn endpoint_shows_the_request_counter() {
+        let state = test_state(StockfishPool::new(vec![fake_engine("")]));
+        state.metrics.requests.fetch_add(3, Ordering::Relaxed);
+
+        let response = metrics(State(state)).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            PROMETHEUS_CONTENT_TYPE
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.lines().any(|line| line == "puzzler_requests_total 3"));
+    }
+}
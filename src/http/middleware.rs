@@ -0,0 +1,82 @@
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::{Instrument, info_span};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Wraps the request in a tracing span carrying its request id
+///
+/// The id is taken from the `X-Request-Id` header when the client sends a usable
+/// one, otherwise a random one is generated. It's echoed back in the response.
+pub async fn request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+
+    let span = info_span!("request", id = %id);
+    let mut response = next.run(request).instrument(span).await;
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, middleware, routing::get};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serves a route behind the middleware, returning its URL
+    async fn serve() -> String {
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn(request_id));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}/")
+    }
+
+    #[tokio::test]
+    async fn client_request_id_is_echoed() {
+        let url = serve().await;
+        let response = reqwest::Client::new()
+            .get(url)
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc-123");
+    }
+
+    #[tokio::test]
+    async fn missing_or_oversized_ids_are_replaced() {
+        let url = serve().await;
+        let client = reqwest::Client::new();
+
+        let generated = client.get(&url).send().await.unwrap();
+        let id = generated.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert_eq!(id.len(), 16);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let too_long = "x".repeat(129);
+        let replaced = client
+            .get(&url)
+            .header(REQUEST_ID_HEADER, &too_long)
+            .send()
+            .await
+            .unwrap();
+        assert_ne!(replaced.headers()[REQUEST_ID_HEADER], too_long.as_str());
+    }
+}
@@ -1,3 +1,8 @@
 pub mod app;
 pub mod handler;
 pub mod error;
+pub mod health;
+pub mod maintenance;
+pub mod metrics;
+pub mod middleware;
+pub mod publish;
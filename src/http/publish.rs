@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::common::config::Config;
+
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Sends a generated puzzle to the Chessland service, retrying on transient
+/// failures: connection errors, timeouts, server errors and rate limiting
+///
+/// Failures are only logged, the puzzle was already returned to the client.
+///
+/// # Arguments
+/// * `client` - Shared HTTP client
+/// * `conf` - Config holding the endpoint and the API key
+/// * `puzzle` - Puzzle already serialized to JSON
+pub async fn publish_puzzle(client: &reqwest::Client, conf: &Config, puzzle: &Value) {
+    for attempt in 1..=MAX_ATTEMPTS {
+        let response = client
+            .post(&conf.chessland_endpoint)
+            .header("x-api-key", &conf.api_key)
+            .json(puzzle)
+            .send()
+            .await;
+
+        let transient = match response {
+            Ok(response) if response.status().is_success() => {
+                info!("published puzzle to chessland");
+                return;
+            }
+            Ok(response) => {
+                let status = response.status();
+                warn!("attempt {attempt}/{MAX_ATTEMPTS} to publish puzzle got status {status}");
+                status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(e) => {
+                warn!("attempt {attempt}/{MAX_ATTEMPTS} to publish puzzle failed: {e}");
+                e.is_connect() || e.is_timeout() || e.is_request()
+            }
+        };
+
+        if !transient {
+            warn!("giving up publishing puzzle, chessland won't accept it");
+            return;
+        }
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(RETRY_BACKOFF * attempt).await;
+        }
+    }
+    warn!("giving up publishing puzzle after {MAX_ATTEMPTS} attempts");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{Json, Router, extract::State, http::HeaderMap, routing::post};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Puzzle received by the mock Chessland service, with its API key
+    type Received = (Option<String>, Value);
+
+    /// Requests the mock Chessland service received, with the statuses it
+    /// answers them with in turn, success once they run out
+    #[derive(Clone, Default)]
+    struct Chessland {
+        received: Arc<Mutex<Vec<Received>>>,
+        statuses: Arc<Mutex<Vec<u16>>>,
+    }
+
+    async fn receive(
+        State(chessland): State<Chessland>,
+        headers: HeaderMap,
+        Json(puzzle): Json<Value>,
+    ) -> axum::http::StatusCode {
+        let api_key = headers
+            .get("x-api-key")
+            .map(|key| key.to_str().unwrap().to_string());
+        chessland.received.lock().unwrap().push((api_key, puzzle));
+
+        let mut statuses = chessland.statuses.lock().unwrap();
+        let status = if statuses.is_empty() {
+            201
+        } else {
+            statuses.remove(0)
+        };
+        axum::http::StatusCode::from_u16(status).unwrap()
+    }
+
+    /// Serves a mock Chessland answering with `statuses`, returning it with a
+    /// config pointing at it
+    async fn serve(statuses: &[u16]) -> (Chessland, Config) {
+        let chessland = Chessland::default();
+        chessland.statuses.lock().unwrap().extend(statuses);
+        let app = Router::new()
+            .route("/api/puzzles", post(receive))
+            .with_state(chessland.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let example = concat!(env!("CARGO_MANIFEST_DIR"), "/config.example.toml");
+        let mut conf = Config::from_toml(example).unwrap();
+        conf.chessland_endpoint = format!("http://{addr}/api/puzzles");
+        (chessland, conf)
+    }
+
+    fn puzzle() -> Value {
+        json!({ "startFen": "r5k1/5ppp/8/8/8/8/4RPPP/4R1K1 w - - 0 1", "moves": ["e2e8"] })
+    }
+
+    #[tokio::test]
+    async fn puzzle_and_api_key_are_forwarded() {
+        let (chessland, conf) = serve(&[]).await;
+
+        publish_puzzle(&reqwest::Client::new(), &conf, &puzzle()).await;
+
+        let received = chessland.received.lock().unwrap();
+        assert_eq!(*received, [(Some("change-me".to_string()), puzzle())]);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let (chessland, conf) = serve(&[503]).await;
+
+        publish_puzzle(&reqwest::Client::new(), &conf, &puzzle()).await;
+
+        assert_eq!(chessland.received.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_puzzles_are_not_retried() {
+        let (chessland, conf) = serve(&[400, 400]).await;
+
+        publish_puzzle(&reqwest::Client::new(), &conf, &puzzle()).await;
+
+        assert_eq!(chessland.received.lock().unwrap().len(), 1);
+    }
+}
@@ -1,3 +1,65 @@
 pub mod common;
 pub mod domain;
 pub mod http;
+
+pub use domain::stockfish;
+
+use std::fmt::Display;
+use std::io;
+
+use domain::pgn::{self, PgnFilter};
+use domain::puzzle::{self, GenerationOptions, Puzzle};
+use stockfish::Stockfish;
+use tracing::warn;
+
+/// Reason puzzles couldn't be generated from a PGN file
+#[derive(Debug)]
+pub enum Error {
+    /// The PGN file couldn't be read
+    Pgn(io::Error),
+    /// The engine couldn't be started
+    Engine(io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Pgn(e) => write!(f, "can't read pgn file: {e}"),
+            Error::Engine(e) => write!(f, "can't start stockfish: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Generates one puzzle per game of a PGN database, with an engine found the
+/// same way as [`Stockfish::try_init`]
+///
+/// Games that can't be read or hold no tactic are logged and skipped rather
+/// than failing the whole file. Games are read one at a time as they are
+/// analyzed, so however large the file only one game is held in memory.
+///
+/// # Arguments
+/// * `path` - Path to the PGN file, possibly gzip-compressed
+/// * `options` - Tuning knobs for the analysis
+/// * `max_games` - Most games to analyze, reading stops once that many were
+///   read; unlimited when `None`
+///
+/// # Returns
+/// The puzzles found, in the order of the games they come from
+pub fn generate_from_pgn_file(
+    path: &str,
+    options: &GenerationOptions,
+    max_games: Option<usize>,
+) -> Result<Vec<Puzzle>, Error> {
+    let mut stockfish = Stockfish::try_init().map_err(Error::Engine)?;
+    let filter = PgnFilter {
+        max_games,
+        ..PgnFilter::default()
+    };
+    let games = pgn::read_pgns_iter_filtered(path, &filter)
+        .map_err(Error::Pgn)?
+        .filter_map(|game| game.inspect_err(|e| warn!("dropping {e}")).ok());
+
+    Ok(puzzle::generate_puzzles_for_games(games, options, &mut stockfish).puzzles)
+}
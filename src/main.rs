@@ -1,26 +1,108 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use chessland_puzzle_generator::domain::puzzle::{GenerationOptions, PuzzleLevel};
 use chessland_puzzle_generator::http::app::app;
-use chessland_puzzle_generator::{common::config::Config, domain::stockfish::Stockfish};
+use chessland_puzzle_generator::{common::config::Config, domain::pool::StockfishPool};
+use clap::{Parser, Subcommand};
 use tracing::{error, info};
 
+/// Environment variable picking the log format, `json` or `text`
+const LOG_FORMAT_VAR: &str = "LOG_FORMAT";
+
+/// Generates chess puzzles out of games, over HTTP or from PGN files
+#[derive(Parser)]
+struct Cli {
+    /// What to run, the HTTP server when left out
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Starts the HTTP server
+    Serve,
+    /// Generates one puzzle per game of a PGN file and writes them as JSON
+    Generate {
+        /// PGN file to read the games from, possibly gzip-compressed
+        #[arg(long)]
+        pgn: String,
+        /// File to write the puzzles to
+        #[arg(long)]
+        out: String,
+        /// Search depth, the level's default when left out
+        #[arg(long)]
+        depth: Option<u8>,
+        /// Stop after this many readable games, every game when left out
+        #[arg(long)]
+        max_games: Option<usize>,
+    },
+}
+
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    init_logging();
     info!("Welcome to puzzler");
 
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve().await,
+        Command::Generate {
+            pgn,
+            out,
+            depth,
+            max_games,
+        } => generate(&pgn, &out, depth, max_games),
+    }
+}
+
+/// Generates puzzles from the games of `pgn`, up to `max_games` of them, and
+/// writes them to `out`, exiting with an error status when either file can't be used
+fn generate(pgn: &str, out: &str, depth: Option<u8>, max_games: Option<usize>) {
+    let options = GenerationOptions {
+        depth,
+        ..GenerationOptions::for_level(PuzzleLevel::default())
+    };
+
+    let puzzles = match chessland_puzzle_generator::generate_from_pgn_file(pgn, &options, max_games)
+    {
+        Ok(puzzles) => puzzles,
+        Err(e) => {
+            error!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let written = File::create(out)
+        .map_err(|e| e.to_string())
+        .and_then(|file| {
+            serde_json::to_writer_pretty(BufWriter::new(file), &puzzles).map_err(|e| e.to_string())
+        });
+    match written {
+        Ok(()) => info!("wrote {} puzzles to {out}", puzzles.len()),
+        Err(e) => {
+            error!("can't write puzzles to {out}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs the HTTP server until a shutdown signal arrives
+async fn serve() {
     let conf = match Config::load() {
         Ok(conf) => {
             info!("Loaded config");
             conf
         }
-        Err(_) => {
-            panic!("unable to get config")
+        Err(e) => {
+            panic!("unable to get config: {e}")
         }
     };
 
-    let stockfish = match Stockfish::try_init() {
-        Ok(stockfish) => {
+    let engines = match StockfishPool::from_config(&conf) {
+        Ok(engines) => {
             info!("initialized stockfish");
-            stockfish
+            engines
         }
         Err(_) => {
             error!("can't initialize stockfish, aborting...");
@@ -28,7 +110,7 @@ async fn main() {
         }
     };
 
-    let app = app(&conf, stockfish);
+    let app = app(&conf, engines.clone());
 
     let listener = tokio::net::TcpListener::bind(format!("{}:{}", conf.host, conf.port))
         .await
@@ -36,5 +118,59 @@ async fn main() {
 
     info!("listening on port {}", conf.port);
     info!("puzzler is up and running");
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+
+    info!("shutting down, quitting engines");
+    match tokio::task::spawn_blocking(move || engines.shutdown()).await {
+        Ok(count) => info!("{count} engines quit, bye"),
+        Err(e) => error!("engine shutdown failed: {e}"),
+    }
+}
+
+/// Completes on ctrl-c, or on SIGTERM on unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("can't listen for ctrl-c: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("can't listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("shutdown signal received");
+}
+
+/// Sets up the log output, one JSON object per line when `LOG_FORMAT` is
+/// `json` so log aggregators can parse it, human readable text otherwise
+fn init_logging() {
+    let format = std::env::var(LOG_FORMAT_VAR).unwrap_or_default();
+    match format.as_str() {
+        "json" => tracing_subscriber::fmt().json().init(),
+        "" | "text" => tracing_subscriber::fmt().init(),
+        other => {
+            tracing_subscriber::fmt().init();
+            error!("unknown {LOG_FORMAT_VAR} {other:?}, logging as text");
+        }
+    }
 }